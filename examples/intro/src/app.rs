@@ -6,27 +6,35 @@ use gfx::{self, Factory};
 use gfx::traits::FactoryExt;
 use nalgebra::{self as na, UnitQuaternion, Similarity3, Translation3, Point2, Vector3};
 
-use lib::{Texture, UberMesh, Error};
+use lib::{UberMesh, Error};
 use lib::mesh::*;
 use lib::load;
-use lib::draw::{DrawParams, Painter, SolidStyle, UberStyle, UberMaterial};
-use lib::vr::{primary, secondary, VrMoment, MappedController, Trackable};
+use lib::draw::{DrawParams, Painter, SolidStyle, LineStyle, LineWidth, UberStyle, UberMaterial, DebugDraw, RenderQueue, Pass};
+use lib::vr::{primary, secondary, VrMoment, MappedController};
+use lib::watch::{ShaderWatcher, Watched};
 
 pub const NEAR_PLANE: f64 = 0.1;
 pub const FAR_PLANE: f64 = 1000.;
 const PI: f32 = ::std::f32::consts::PI;
 const PI2: f32 = 2. * PI;
 const DEG: f32 = PI2 / 360.;
+const TEAPOT_FILES: [&str; 4] = [
+    "assets/cerberus/model.obj",
+    "assets/cerberus/albedo.png",
+    "assets/cerberus/normal.png",
+    "assets/cerberus/knobs.png",
+];
 
-pub struct App<R: gfx::Resources> {
+pub struct App<R: gfx::Resources, F> {
     solid: Painter<R, SolidStyle<R>>,
+    lines: Painter<R, LineStyle<R>>,
     uber: Painter<R, UberStyle<R>>,
     grid: Mesh<R, VertC, ()>,
     bg_mesh: Mesh<R, VertC, ()>,
-    controller_grid: Mesh<R, VertC, ()>,
-    arrow: Mesh<R, VertC, ()>,
+    debug: DebugDraw<R>,
     controller: UberMesh<R>,
-    teapot: UberMesh<R>,
+    teapot: Watched<F, UberMesh<R>>,
+    shader_watcher: ShaderWatcher,
     start_time: Instant,
     primary: MappedController,
     secondary: MappedController,
@@ -79,22 +87,6 @@ fn grid_lines(count: u32, size: f32) -> MeshSource<VertC, ()> {
     }
 }
 
-fn arrow() -> MeshSource<VertC, ()> {
-    MeshSource {
-        verts: vec![
-            VertC { pos: [0., 0., 0.], color: [0., 0., 0.] },
-            VertC { pos: [0., 0., 1.], color: [0., 0., 0.] },
-            VertC { pos: [0.1, 0., 0.9], color: [0., 0., 0.] },
-            VertC { pos: [0., 0., 1.], color: [0., 0., 0.] },
-            VertC { pos: [-0.1, 0., 0.9], color: [0., 0., 0.] },
-            VertC { pos: [0., 0., 1.], color: [0., 0., 0.] },
-        ],
-        inds: Indexing::All,
-        prim: Primitive::LineList,
-        mat: (),
-    }
-}
-
 fn load_my_simple_object<P, R, F>(
     f: &mut F,
     path: P,
@@ -106,27 +98,26 @@ fn load_my_simple_object<P, R, F>(
     -> Result<Mesh<R, VertNTT, UberMaterial<R>>, Error>
     where P: AsRef<Path>, R: gfx::Resources, F: gfx::Factory<R>
 {
-    fn f2unorm(v: f32) -> u8 {
-        (v * 256.).round().min(255.).max(0.) as u8
-    }
-
-    let albedo = [f2unorm(albedo[0]), f2unorm(albedo[1]), f2unorm(albedo[2]), 255];
-    let knobs = [f2unorm(metalness), f2unorm(roughness), f2unorm(flatness), 0];
-    use gfx::format::*;
     Ok(load::open_wavefront(path)?.compute_tan().with_material(UberMaterial {
-        albedo: Texture::<_, (R8_G8_B8_A8, Srgb)>::uniform_value(f, albedo)?,
-        normal: Texture::<_, (R8_G8_B8_A8, Unorm)>::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
-        knobs: Texture::<_, (R8_G8_B8_A8, Unorm)>::uniform_value(f, knobs)?,
+        albedo_factor: [albedo[0], albedo[1], albedo[2], 1.],
+        metalness_factor: metalness,
+        roughness_factor: roughness,
+        flatness_factor: flatness,
+        .. Default::default()
     }).upload(f))
 }
 
-impl<R: gfx::Resources> App<R> {
-    pub fn new<F: Factory<R> + FactoryExt<R>>(factory: &mut F) -> Result<Self, Error> {
+impl<R: gfx::Resources, F: Factory<R> + FactoryExt<R>> App<R, F> {
+    pub fn new(factory: &mut F) -> Result<Self, Error> {
         // Setup Painters
         let mut solid = Painter::new(factory)?;
         solid.setup(factory, Primitive::LineList)?;
         solid.setup(factory, Primitive::TriangleList)?;
 
+        let mut lines: Painter<_, LineStyle<_>> = Painter::new(factory)?;
+        lines.setup(factory, Primitive::LineList)?;
+        lines.cfg(|c| c.width(LineWidth::Pixels(2.5)));
+
         let mut uber: Painter<_, UberStyle<_>> = Painter::new(factory)?;
         uber.setup(factory, Primitive::TriangleList)?;
 
@@ -182,11 +173,11 @@ impl<R: gfx::Resources> App<R> {
         // Construct App
         Ok(App {
             solid: solid,
+            lines: lines,
             uber: uber,
             grid: grid_lines(8, 8.).upload(factory),
             bg_mesh: bg_mesh,
-            controller_grid: grid_lines(2, 0.2).upload(factory),
-            arrow: arrow().upload(factory),
+            debug: DebugDraw::new(factory)?,
             controller: load_my_simple_object(
                 factory,
                 "assets/controller.obj",
@@ -194,12 +185,13 @@ impl<R: gfx::Resources> App<R> {
                 1.,
                 0.1,
                 0.)?,
-            teapot: load::open_uber_mesh(
-                factory, 
-                "assets/cerberus/model.obj",
-                "assets/cerberus/albedo.png",
-                "assets/cerberus/normal.png",
-                "assets/cerberus/knobs.png")?,
+            teapot: Watched::new(factory, &TEAPOT_FILES, |f| load::open_uber_mesh(
+                f,
+                TEAPOT_FILES[0],
+                TEAPOT_FILES[1],
+                TEAPOT_FILES[2],
+                TEAPOT_FILES[3]))?,
+            shader_watcher: ShaderWatcher::new(),
             start_time: Instant::now(),
             primary: MappedController {
                 is: primary(),
@@ -213,6 +205,31 @@ impl<R: gfx::Resources> App<R> {
         })
     }
 
+    /// Follow the exposure of the post chain, which flat materials are blended in after.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.uber.cfg(|inputs| inputs.set_exposure(exposure));
+    }
+
+    /// Reload shaders and assets that have changed on disk, and compile the shader variants
+    /// that drawing has needed.
+    pub fn reload(&mut self, factory: &mut F) {
+        if self.shader_watcher.changed() {
+            info!("Reloading shaders");
+            self.solid.reload(factory);
+            self.lines.reload(factory);
+            self.uber.reload(factory);
+        }
+        self.teapot.update(factory);
+        // Compile the shader variants needed by the materials and stereo targets drawn
+        let prepared = self.solid.prepare(factory)
+            .and(self.lines.prepare(factory))
+            .and(self.uber.prepare(factory))
+            .and(self.debug.prepare(factory));
+        if let Err(e) = prepared {
+            error!("Could not compile shader variants: {}", e);
+        }
+    }
+
     pub fn draw<C: gfx::CommandBuffer<R>>(
         &mut self,
         ctx: &mut DrawParams<R, C>,
@@ -227,12 +244,8 @@ impl<R: gfx::Resources> App<R> {
         }
 
         // Clear targets
-        ctx.encoder.clear_depth(&ctx.depth, FAR_PLANE as f32);
-        ctx.encoder.clear(&ctx.color, [0., 0., 0., 0.]);
+        ctx.clear([0., 0., 0., 0.], FAR_PLANE as f32);
         self.uber.clear_env(ctx);
-        
-        // Draw grid
-        self.solid.draw(ctx, na::one(), &self.grid);
         //self.solid.draw(ctx, na::one(), &self.bg_mesh);
 
         // Draw teapot
@@ -254,35 +267,22 @@ impl<R: gfx::Resources> App<R> {
                 1.,
             )
         };
-        self.uber.draw(ctx, na::convert(teamat), &self.teapot);
-
-        // Draw controllers
+        // Queue the scene, so that draws are sorted by state and depth
+        let mut queue = RenderQueue::new();
+        let mut queued = self.uber.queue(&mut queue, Pass::Opaque, na::convert(teamat), &self.teapot);
         for cont in vrm.controllers() {
-            self.solid.draw(ctx, na::convert(cont.pose), &self.controller_grid);
-            self.uber.draw(ctx, na::convert(cont.pose), &self.controller);
+            queued = queued.and(self.uber.queue(&mut queue, Pass::Opaque, na::convert(cont.pose), &self.controller));
+        }
+        // Blended lines go after opaque objects
+        queued = queued.and(self.lines.queue(&mut queue, Pass::Transparent, na::one(), &self.grid));
+        if let Err(e) = queued {
+            error!("Could not queue the scene: {}", e);
         }
+        queue.submit(ctx);
 
         for cont in &[&self.primary, &self.secondary] {
-            let scale = na::norm(&cont.lin_vel);
-            if scale > ::std::f32::EPSILON {
-                let mat = Similarity3::new_observer_frame(
-                    &cont.origin(),
-                    &(cont.origin() + cont.lin_vel),
-                    &Vector3::y(),
-                    scale,
-                );
-                self.solid.draw(ctx, na::convert(mat), &self.arrow);
-            }
-            let scale = na::norm(&cont.ang_vel) / 6.28;
-            if scale > ::std::f32::EPSILON {
-                let mat = Similarity3::new_observer_frame(
-                    &cont.origin(),
-                    &(cont.origin() + cont.ang_vel),
-                    &Vector3::y(),
-                    scale,
-                );
-                self.solid.draw(ctx, na::convert(mat), &self.arrow);
-            }
+            self.debug.controller(cont, [0., 0., 0.]);
         }
+        self.debug.flush(ctx);
     }
 }
@@ -0,0 +1,81 @@
+use gfx::Encoder;
+use gfx_device_gl::{Device, Resources, CommandBuffer};
+use gl;
+use gl::types::GLuint;
+use lib::draw::GpuTimer;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Times passes with `GL_TIME_ELAPSED` queries. Results are read back once the GPU has
+/// finished with them, so the times reported for a pass lag a few frames behind. The
+/// queries are never deleted, since they are few and freed along with the GL context.
+#[derive(Default)]
+pub struct GlTimer {
+    free: Vec<GLuint>,
+    pending: VecDeque<(String, GLuint)>,
+    times: HashMap<String, Duration>,
+}
+
+impl GlTimer {
+    pub fn new() -> GlTimer {
+        Default::default()
+    }
+
+    /// Time the passes of a frame drawn with the given device.
+    pub fn frame<'d>(&'d mut self, device: &'d mut Device) -> GlTimerFrame<'d> {
+        GlTimerFrame {
+            timer: self,
+            device: device,
+        }
+    }
+
+    /// Read back the queries that have finished, in the order they were issued.
+    fn collect(&mut self, gl: &gl::Gl) {
+        while let Some(&(_, query)) = self.pending.front() {
+            let mut available = 0;
+            unsafe { gl.GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available) };
+            if available == 0 { break }
+            let mut nanos = 0;
+            unsafe { gl.GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanos) };
+            let (name, query) = self.pending.pop_front().unwrap();
+            self.times.insert(name, Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32));
+            self.free.push(query);
+        }
+    }
+}
+
+/// A `GlTimer` borrowed for a frame, along with the device it flushes passes to.
+pub struct GlTimerFrame<'d> {
+    timer: &'d mut GlTimer,
+    device: &'d mut Device,
+}
+
+impl<'d> GpuTimer<Resources, CommandBuffer> for GlTimerFrame<'d> {
+    fn begin(&mut self, enc: &mut Encoder<Resources, CommandBuffer>, pass: &str) {
+        enc.flush(self.device);
+        let timer = &mut *self.timer;
+        unsafe {
+            self.device.with_gl(|gl| {
+                let query = timer.free.pop().unwrap_or_else(|| {
+                    let mut query = 0;
+                    gl.GenQueries(1, &mut query);
+                    query
+                });
+                gl.BeginQuery(gl::TIME_ELAPSED, query);
+                timer.pending.push_back((pass.to_owned(), query));
+            });
+        }
+    }
+
+    fn end(&mut self, enc: &mut Encoder<Resources, CommandBuffer>, pass: &str) -> Option<Duration> {
+        enc.flush(self.device);
+        let timer = &mut *self.timer;
+        unsafe {
+            self.device.with_gl(|gl| {
+                gl.EndQuery(gl::TIME_ELAPSED);
+                timer.collect(gl);
+            });
+        }
+        timer.times.get(pass).cloned()
+    }
+}
@@ -8,6 +8,7 @@ extern crate gfx;
 extern crate nalgebra;
 extern crate glutin;
 extern crate gfx_device_gl;
+extern crate gfx_gl as gl;
 extern crate gfx_window_glutin;
 
 use simplelog::{Config, TermLogger, LogLevelFilter};
@@ -17,8 +18,10 @@ use gfx::format::*;
 use gfx_device_gl::{NewTexture};
 use gfx::memory::{Typed, Bind};
 use glutin::GlContext;
+use std::time::{Duration, Instant};
 
 mod app;
+mod gl_timer;
 
 use lib::draw;
 use lib::vr::*;
@@ -33,8 +36,37 @@ fn main() {
             .short("m")
             .long("mock")
             .help("Use mock VR API"))
+        .arg(Arg::with_name("samples")
+            .short("s")
+            .long("samples")
+            .takes_value(true)
+            .help("Number of MSAA samples per pixel"))
+        .arg(Arg::with_name("spectator")
+            .long("spectator")
+            .takes_value(true)
+            .possible_values(&["head", "third", "left", "right"])
+            .help("Show a spectator view in the desktop window"))
+        .arg(Arg::with_name("shaders")
+            .long("shaders")
+            .takes_value(true)
+            .help("Load shaders from this directory, reloading them when they change"))
+        .arg(Arg::with_name("stats")
+            .long("stats")
+            .help("Log rendering statistics, with GPU times, every second"))
         .get_matches();
     let mock = matches.is_present("mock");
+    let log_stats = matches.is_present("stats");
+    if let Some(dir) = matches.value_of("shaders") {
+        draw::set_shader_dir(Some(dir));
+    }
+    let samples = match matches.value_of("samples").map(|s| s.parse::<u8>()) {
+        Some(Ok(s)) => s,
+        Some(Err(e)) => {
+            error!("Invalid sample count: {}", e);
+            return
+        },
+        None => 4,
+    };
 
     // VR init
     let mut vrctx = match if mock { VrContext::mock() } else { VrContext::new() } {
@@ -51,6 +83,7 @@ fn main() {
 
     // Get some frame sizeing information
     let (render_width, render_height) = vrctx.retrieve_size();
+    let (eye_width, eye_height) = vrctx.retrieve_eye_size();
 
     // Window manager stuff
     let mut events_loop = glutin::EventsLoop::new();
@@ -60,7 +93,7 @@ fn main() {
         .with_title("Mock OpenVR Display");
     let context = glutin::ContextBuilder::new();
     // Fuuny thing I found here: changing `_window` to `_` (ignoring it) makes everything explode because of early drop.
-    let (window, mut device, mut factory, wcolor, wdepth) =
+    let (window, mut device, mut factory, wcolor, _) =
         gfx_window_glutin::init::<Rgba8, DepthStencil>(window_builder, context, &events_loop);
 
     // Create texture to render to
@@ -83,10 +116,37 @@ fn main() {
     };
     vrctx.set_texture(texture_id);
 
-    // Create depth buffer
-    let (.., depth) = factory.create_depth_stencil(render_width as u16, render_height as u16).unwrap();
+    // Create HDR buffers for the painters and the post-processing that resolves them
+    let hdr = draw::HdrTarget::new_multisampled(
+        &mut factory,
+        eye_width as u16,
+        eye_height as u16,
+        2,
+        samples,
+    ).unwrap();
+    let mut post = draw::PostChain::new(&mut factory, eye_width as u16, eye_height as u16).unwrap();
+    post.auto_exposure = Some(Default::default());
 
     let surface = factory.view_texture_as_render_target::<(R8_G8_B8_A8, Unorm)>(&tex, 0, None).unwrap();
+    let mut spectator = matches.value_of("spectator").map(|mode| {
+        let mut s = draw::Spectator::new(&mut factory, render_width as u16, render_height as u16, samples).unwrap();
+        s.near = app::NEAR_PLANE as f32;
+        s.far = app::FAR_PLANE as f32;
+        match mode {
+            "head" => s.mode = draw::SpectatorMode::HeadFollow,
+            "left" => s.mode = draw::SpectatorMode::Eye(0),
+            "right" => s.mode = draw::SpectatorMode::Eye(1),
+            _ => (),
+        }
+        s
+    });
+    let show_window = mock || spectator.is_some();
+
+    // Press F12 in the window to save the frame
+    let mut capture = draw::FrameCapture::new(&mut factory, render_width as u16, render_height as u16, &hdr, &post).unwrap();
+    let mut capture_requested = false;
+    let mut captures = 0;
+    let output = if mock && spectator.is_none() { wcolor.clone() } else { surface };
     let mut application = match app::App::new(&mut factory) {
         Ok(a) => a,
         Err(e) => {
@@ -98,17 +158,22 @@ fn main() {
     // setup context
     let mut ctx = draw::DrawParams {
         encoder: factory.create_command_buffer().into(),
-        color: if mock { wcolor } else { surface },
-        depth: if mock { wdepth } else { depth },
-        left: Default::default(),
-        right: Default::default(),
+        views: hdr.eyes.iter().map(|t| draw::View {
+            eye: Default::default(),
+            target: t.clone(),
+        }).collect(),
+        stereo: Some(hdr.layered.clone()),
+        stats: Default::default(),
     };
 
-    if mock { window.show() }
+    if show_window { window.show() }
 
     // Main loop
     vrctx.start();
     let mut running = true;
+    let mut last_frame = Instant::now();
+    let mut last_stats = Instant::now();
+    let mut gpu_timer = gl_timer::GlTimer::new();
     while running {
         let vrm = vrctx.sync();
         let hmd = match vrm.hmd() {
@@ -118,19 +183,87 @@ fn main() {
 
         // Update context
         running = !vrm.exit;
-        ctx.left = hmd.left;
-        ctx.right = hmd.right;
+        let now = Instant::now();
+        let dt = now - last_frame;
+        let dt = dt.as_secs() as f32 + dt.subsec_nanos() as f32 * 1e-9;
+        last_frame = now;
+        ctx.views[0].eye = hmd.left;
+        ctx.views[1].eye = hmd.right;
+        ctx.views.truncate(2);
+        if let Some(ref mut s) = spectator {
+            s.update(&hmd.pose, dt);
+            ctx.views.extend(s.view());
+        }
+
+        // Adapt exposure to the last frame
+        if let Err(e) = post.adapt(&mut factory, dt) {
+            warn!("Could not adapt exposure: {}", e);
+        }
+        application.set_exposure(post.current_exposure());
 
-        // Draw frame
-        application.draw(&mut ctx, &vrm);
+        // Draw frame, timing the passes on the GPU when logging statistics
+        application.reload(&mut factory);
+        {
+            let mut timer = gpu_timer.frame(&mut device);
+            let mut timer = if log_stats { Some(&mut timer) } else { None };
+            let _ = ctx.pass("scene", timer.as_mut().map(|t| &mut **t as &mut draw::GpuTimer<_, _>), |ctx| {
+                application.draw(ctx, &vrm);
+                Ok(())
+            });
+
+            // Tone map into the displayed texture
+            let result = ctx.pass("post", timer.as_mut().map(|t| &mut **t as &mut draw::GpuTimer<_, _>), |ctx| {
+                let eyes = [&ctx.views[0].eye, &ctx.views[1].eye];
+                post.apply(&mut ctx.encoder, &hdr, &output, &eyes)
+            });
+            if let Err(e) = result {
+                error!("Could not post-process frame: {}", e);
+            }
+        }
+        if capture_requested {
+            capture_requested = false;
+            let eyes = [&ctx.views[0].eye, &ctx.views[1].eye];
+            if let Err(e) = capture.capture(&mut ctx.encoder, &post, &hdr, &eyes, true) {
+                error!("Could not capture frame: {}", e);
+            }
+        }
+        if let Some(ref mut s) = spectator {
+            s.post.exposure = post.exposure;
+            if let Err(e) = s.present(&mut ctx.encoder, &hdr, &wcolor) {
+                error!("Could not draw spectator view: {}", e);
+            }
+        }
 
         // Send instructions to OpenGL
         // TODO: Move flush to separate thread
         ctx.encoder.flush(&mut device);
+        let stats = ctx.take_stats();
+        if log_stats && last_stats.elapsed() >= Duration::from_secs(1) {
+            last_stats = Instant::now();
+            info!("{}", stats);
+        }
+
+        // Save captured frames
+        match capture.read(&mut factory) {
+            Ok(Some(frame)) => {
+                let saved = frame.save_png(format!("capture-{}.png", captures))
+                    .and_then(|_| frame.save_eye_png(0, format!("capture-{}-left.png", captures)))
+                    .and_then(|_| frame.save_eye_png(1, format!("capture-{}-right.png", captures)))
+                    .and_then(|_| frame.save_hdr(0, format!("capture-{}-left.hdr", captures)))
+                    .and_then(|_| frame.save_hdr(1, format!("capture-{}-right.hdr", captures)));
+                match saved {
+                    Ok(()) => info!("Saved capture {}", captures),
+                    Err(e) => error!("Could not save capture: {}", e),
+                }
+                captures += 1;
+            },
+            Ok(None) => (),
+            Err(e) => error!("Could not read back capture: {}", e),
+        }
 
         // Send resulting texture to VR device
         vrm.submit(&mut vrctx);
-        if mock { window.swap_buffers().unwrap() }
+        if show_window { window.swap_buffers().unwrap() }
 
         // Cleanup GFX data
         device.cleanup();
@@ -141,6 +274,14 @@ fn main() {
                 // process events here
                 glutin::Event::WindowEvent { event: glutin::WindowEvent::Closed, .. } =>
                     running = false,
+                glutin::Event::WindowEvent { event: glutin::WindowEvent::KeyboardInput {
+                    input: glutin::KeyboardInput {
+                        state: glutin::ElementState::Pressed,
+                        virtual_keycode: Some(glutin::VirtualKeyCode::F12),
+                        ..
+                    },
+                    ..
+                }, .. } => capture_requested = true,
                 _ => ()
             }
         });
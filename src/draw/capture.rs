@@ -0,0 +1,224 @@
+use gfx::{Resources, CommandBuffer, Factory, Encoder, Rect};
+use gfx::handle::{self, Buffer, RenderTargetView};
+use gfx::texture::{Kind, AaMode};
+use gfx::memory::{Bind, Usage};
+use gfx::format::{self, R8_G8_B8_A8, ChannelType, SurfaceType, Unorm, Srgb};
+use image::{self, hdr, Rgb, RgbaImage};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::f32;
+
+use super::{EyeParams, PostChain, HdrTarget};
+use ::Error;
+
+/// Reads back finished frames so they can be saved as image files. Captures are
+/// queued on an encoder and read once the encoder has been flushed.
+pub struct FrameCapture<R: Resources> {
+    texture: handle::Texture<R, R8_G8_B8_A8>,
+    target: CaptureTarget<R>,
+    download: Buffer<R, [u8; 4]>,
+    hdr_download: Buffer<R, [u16; 4]>,
+    size: (u16, u16),
+    hdr_size: (u16, u16, u16),
+    pending: Option<(Vec<Rect>, bool)>,
+}
+
+/// A frame read back by `FrameCapture`
+pub struct CapturedFrame {
+    /// The displayed image with all eyes side by side, top row first
+    pub image: RgbaImage,
+    /// The viewport of each eye in `image` (with a bottom-left origin, like `EyeParams::clip`)
+    pub eyes: Vec<Rect>,
+    /// The HDR color of each eye before post-processing, top row first (empty unless requested)
+    pub hdr: Vec<Vec<Rgb<f32>>>,
+    /// The width and height of each HDR image
+    pub hdr_size: (u32, u32),
+}
+
+/// The target that captured frames are post-processed into, in the output format of the
+/// post chain
+enum CaptureTarget<R: Resources> {
+    Unorm(RenderTargetView<R, (R8_G8_B8_A8, Unorm)>),
+    Srgb(RenderTargetView<R, (R8_G8_B8_A8, Srgb)>),
+}
+
+/// Clear the target and post-process each eye's layer of the source into its viewport.
+fn post_eyes<R, C, T>(
+    enc: &mut Encoder<R, C>,
+    post: &PostChain<R>,
+    source: &HdrTarget<R>,
+    target: &RenderTargetView<R, T>,
+    eyes: &[&EyeParams],
+) -> Result<(), Error>
+    where R: Resources, C: CommandBuffer<R>, T: format::RenderFormat<View = [f32; 4]>
+{
+    enc.clear(target, [0., 0., 0., 1.]);
+    for (i, eye) in eyes.iter().enumerate() {
+        post.apply_layer(enc, source, target, i, eye.clip)?;
+    }
+    Ok(())
+}
+
+/// Convert a half-precision float to single precision.
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1. } else { 1. };
+    let exp = ((h >> 10) & 0x1F) as i32;
+    let frac = (h & 0x3FF) as f32;
+    sign * match exp {
+        0 => frac * 2f32.powi(-24),
+        31 => if frac == 0. { f32::INFINITY } else { f32::NAN },
+        e => (1. + frac / 1024.) * 2f32.powi(e - 15),
+    }
+}
+
+impl<R: Resources> FrameCapture<R> {
+    /// Prepare to capture frames of the given (side by side) display size,
+    /// post-processed from the given HDR target by the given chain. The chain must write
+    /// `R8_G8_B8_A8` targets (with `Unorm` or `Srgb` channels), which frames are captured in.
+    pub fn new<F: Factory<R>>(f: &mut F, width: u16, height: u16, source: &HdrTarget<R>, post: &PostChain<R>)
+        -> Result<FrameCapture<R>, Error>
+    {
+        let format = post.output_format();
+        ensure!(format.0 == SurfaceType::R8_G8_B8_A8 && (format.1 == ChannelType::Unorm || format.1 == ChannelType::Srgb),
+            "Cannot capture frames of a post chain that writes {:?}", format);
+        let texture = f.create_texture(
+            Kind::D2(width, height, AaMode::Single),
+            1,
+            Bind::RENDER_TARGET | Bind::TRANSFER_SRC,
+            Usage::Data,
+            Some(format.1))?;
+        let target = if format.1 == ChannelType::Srgb {
+            CaptureTarget::Srgb(f.view_texture_as_render_target(&texture, 0, None)?)
+        } else {
+            CaptureTarget::Unorm(f.view_texture_as_render_target(&texture, 0, None)?)
+        };
+        let layers = source.eyes.len();
+        let (hw, hh) = source.size;
+        Ok(FrameCapture {
+            texture: texture,
+            target: target,
+            download: f.create_download_buffer(width as usize * height as usize)?,
+            hdr_download: f.create_download_buffer(hw as usize * hh as usize * layers)?,
+            size: (width, height),
+            hdr_size: (hw, hh, layers as u16),
+            pending: None,
+        })
+    }
+
+    /// Queue a capture of the current frame: each eye's layer of the source is post-processed
+    /// into its viewport, and optionally the HDR colors are captured as well. The chain
+    /// must have the output format of the one the capture was created with.
+    pub fn capture<C: CommandBuffer<R>>(
+        &mut self,
+        enc: &mut Encoder<R, C>,
+        post: &PostChain<R>,
+        source: &HdrTarget<R>,
+        eyes: &[&EyeParams],
+        hdr: bool,
+    ) -> Result<(), Error> {
+        ensure!(eyes.len() <= self.hdr_size.2 as usize, "Cannot capture more eyes than the source has");
+        let channel = match self.target {
+            CaptureTarget::Unorm(ref t) => { post_eyes(enc, post, source, t, eyes)?; ChannelType::Unorm },
+            CaptureTarget::Srgb(ref t) => { post_eyes(enc, post, source, t, eyes)?; ChannelType::Srgb },
+        };
+        let info = self.texture.get_info().to_raw_image_info(channel, 0);
+        enc.copy_texture_to_buffer_raw(self.texture.raw(), None, info, self.download.raw(), 0)
+            .map_err(|e| format_err!("Could not read back frame: {:?}", e))?;
+
+        if hdr {
+            let (w, h, _) = self.hdr_size;
+            let layer_bytes = w as usize * h as usize * 8;
+            let tex = source.color_texture();
+            for i in 0..eyes.len() {
+                let mut info = tex.get_info().to_raw_image_info(ChannelType::Float, 0);
+                info.zoffset = i as u16;
+                info.depth = 1;
+                enc.copy_texture_to_buffer_raw(tex.raw(), None, info, self.hdr_download.raw(), i * layer_bytes)
+                    .map_err(|e| format_err!("Could not read back HDR frame: {:?}", e))?;
+            }
+        }
+
+        self.pending = Some((eyes.iter().map(|e| e.clip).collect(), hdr));
+        Ok(())
+    }
+
+    /// Read the frame queued by `capture`, if any. The encoder used for capturing
+    /// must have been flushed.
+    pub fn read<F: Factory<R>>(&mut self, f: &mut F) -> Result<Option<CapturedFrame>, Error> {
+        let (eyes, hdr) = match self.pending.take() {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        let (w, h) = (self.size.0 as u32, self.size.1 as u32);
+        let data: Vec<u8> = f.read_mapping(&self.download)?.iter()
+            .flat_map(|p| p.iter().cloned())
+            .collect();
+        let image = RgbaImage::from_raw(w, h, data)
+            .ok_or_else(|| format_err!("Captured frame has the wrong size"))?;
+
+        let (hw, hh, _) = self.hdr_size;
+        let mut hdr_images = Vec::new();
+        if hdr {
+            let data = f.read_mapping(&self.hdr_download)?;
+            let layer = hw as usize * hh as usize;
+            for i in 0..eyes.len() {
+                // rows are read bottom first
+                hdr_images.push(data[i * layer..(i + 1) * layer]
+                    .chunks(hw as usize)
+                    .rev()
+                    .flat_map(|row| row.iter())
+                    .map(|p| Rgb { data: [half_to_f32(p[0]), half_to_f32(p[1]), half_to_f32(p[2])] })
+                    .collect());
+            }
+        }
+
+        Ok(Some(CapturedFrame {
+            image: image::imageops::flip_vertical(&image),
+            eyes: eyes,
+            hdr: hdr_images,
+            hdr_size: (hw as u32, hh as u32),
+        }))
+    }
+}
+
+impl CapturedFrame {
+    /// The image of a single eye, cropped from the side by side image.
+    pub fn eye_image(&self, eye: usize) -> Result<RgbaImage, Error> {
+        let r = *self.eyes.get(eye).ok_or_else(|| format_err!("There is no eye {} in the frame", eye))?;
+        let mut image = self.image.clone();
+        let top = self.image.height().saturating_sub(r.y as u32 + r.h as u32);
+        Ok(image::imageops::crop(&mut image, r.x as u32, top, r.w as u32, r.h as u32).to_image())
+    }
+
+    /// Save the side by side image as a PNG file.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.image.save(path)?;
+        Ok(())
+    }
+
+    /// Save the image of a single eye as a PNG file.
+    pub fn save_eye_png<P: AsRef<Path>>(&self, eye: usize, path: P) -> Result<(), Error> {
+        self.eye_image(eye)?.save(path)?;
+        Ok(())
+    }
+
+    /// Save the HDR colors of a single eye as a Radiance HDR file.
+    pub fn save_hdr<P: AsRef<Path>>(&self, eye: usize, path: P) -> Result<(), Error> {
+        let img = self.hdr.get(eye).ok_or_else(|| format_err!("No HDR image was captured for eye {}", eye))?;
+        let file = BufWriter::new(File::create(path)?);
+        hdr::HDREncoder::new(file).encode(img, self.hdr_size.0 as usize, self.hdr_size.1 as usize)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn half_floats() {
+    assert_eq!(half_to_f32(0x0000), 0.);
+    assert_eq!(half_to_f32(0x3C00), 1.);
+    assert_eq!(half_to_f32(0xC000), -2.);
+    assert_eq!(half_to_f32(0x7BFF), 65504.);
+    assert_eq!(half_to_f32(0x0001), 2f32.powi(-24));
+    assert!(half_to_f32(0x7C00).is_infinite());
+}
@@ -1,7 +1,13 @@
 use gfx::{Rect, Encoder, Resources, CommandBuffer};
-use nalgebra::{self as na, Transform3, Point3};
+use gfx::format::{RenderFormat, DepthFormat, ChannelType, R8_G8_B8_A8, Int, Uint};
+use gfx::handle::{RenderTargetView, DepthStencilView, RawRenderTargetView, RawDepthStencilView};
+use gfx::memory::Typed;
+use nalgebra::{self as na, Transform3, Point3, Isometry3, Perspective3};
+use std::mem;
+use std::time::Instant;
 
-use ::{DepthRef, TargetRef};
+use super::{FrameStats, PassTime, GpuTimer};
+use ::{DepthRef, HdrTargetRef, Error};
 
 /// Parameters that control the rendering of an eye
 #[derive(Copy, Clone)]
@@ -9,32 +15,178 @@ pub struct EyeParams {
     pub eye: Point3<f32>,
     pub view: Transform3<f32>,
     pub proj: Transform3<f32>,
-    pub clip_offset: f32,
+    /// The viewport of this eye in the displayed (submitted) texture
     pub clip: Rect,
 }
 
+impl EyeParams {
+    /// Build eye parameters for a perspective camera at the given pose (looking down its
+    /// -z axis), with a vertical field of view (radians), aspect ratio (width / height), and
+    /// near and far clipping planes. The viewport (`clip`) is left empty.
+    pub fn from_camera(pose: Isometry3<f32>, fovy: f32, aspect: f32, near: f32, far: f32) -> EyeParams {
+        EyeParams {
+            eye: Point3::from_coordinates(pose.translation.vector),
+            view: Transform3::from_matrix_unchecked(pose.inverse().to_homogeneous()),
+            proj: Transform3::from_matrix_unchecked(Perspective3::new(aspect, fovy, near, far).to_homogeneous()),
+            .. Default::default()
+        }
+    }
+
+    /// Build eye parameters for a perspective camera drawn into the given viewport, using
+    /// the aspect ratio of the viewport (see `from_camera`).
+    pub fn from_camera_viewport(pose: Isometry3<f32>, fovy: f32, near: f32, far: f32, clip: Rect) -> EyeParams {
+        let aspect = clip.w as f32 / (clip.h as f32).max(1.);
+        EyeParams {
+            clip: clip,
+            .. EyeParams::from_camera(pose, fovy, aspect, near, far)
+        }
+    }
+}
+
 impl Default for EyeParams {
     fn default() -> EyeParams {
         EyeParams {
             eye: Point3::origin(),
             view: na::one(),
             proj: na::one(),
-            clip_offset: 0.,
             clip: Rect { x: 0, y: 0, w: 0, h: 0 },
         }
     }
 }
 
+/// The color and depth targets a single eye is drawn into. The whole target is
+/// the eye's viewport; the `PostChain` later places it into the eye's `clip` rectangle.
+/// The targets may have any format, as long as the painters drawing into them were set
+/// up for it (see `RasterOptions::formats`).
+#[derive(Clone)]
+pub struct EyeTarget<R: Resources> {
+    /// The color draw target, usually HDR (see `PostChain`). Its alpha is opacity.
+    pub color: RawRenderTargetView<R>,
+    /// The depth draw target
+    pub depth: RawDepthStencilView<R>,
+    /// The channel type of the color target, for clearing it
+    color_channel: ChannelType,
+}
+
+impl<R: Resources> EyeTarget<R> {
+    /// Draw into the given color and depth targets.
+    pub fn new<C: RenderFormat, D: DepthFormat>(color: &RenderTargetView<R, C>, depth: &DepthStencilView<R, D>)
+        -> EyeTarget<R>
+    {
+        EyeTarget {
+            color: color.raw().clone(),
+            depth: depth.raw().clone(),
+            color_channel: C::get_format().1,
+        }
+    }
+}
+
+/// The number of views rendered by a single draw call
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ViewMode {
+    /// Draw a single view into a regular target
+    Single,
+    /// Draw both eyes at once into a layered target (see `DrawParams::stereo`)
+    Stereo,
+}
+
+/// One of the targets of `DrawParams` that meshes are drawn into
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DrawTarget {
+    /// The layered stereo target, covering the first two views
+    Stereo,
+    /// The view with the given index, drawn on its own
+    View(usize),
+}
+
+/// A single view of the scene: the parameters of the eye (or camera) and the targets it is drawn into
+#[derive(Clone)]
+pub struct View<R: Resources> {
+    /// The eye parameters
+    pub eye: EyeParams,
+    /// The draw targets
+    pub target: EyeTarget<R>,
+}
+
 /// Parameters to the draw system
 pub struct DrawParams<R: Resources, C: CommandBuffer<R>> {
     /// The gfx command encoder
     pub encoder: Encoder<R, C>,
-    /// The color draw target
-    pub color: TargetRef<R>,
-    /// The depth draw target
-    pub depth: DepthRef<R>,
-    /// Left eye parameters
-    pub left: EyeParams,
-    /// Right eye parameters
-    pub right: EyeParams,
-}
\ No newline at end of file
+    /// The views to draw, e.g. the left and right eyes of an HMD or a single desktop camera
+    pub views: Vec<View<R>>,
+    /// Layered targets covering the first two views. When set, painters draw both with a
+    /// single draw call instead of drawing each view separately. Any further views are
+    /// still drawn separately.
+    pub stereo: Option<EyeTarget<R>>,
+    /// The rendering statistics of the current frame
+    pub stats: FrameStats,
+}
+
+impl<R: Resources, C: CommandBuffer<R>> DrawParams<R, C> {
+    /// The targets that each mesh is drawn into: the stereo target if it is set (and
+    /// there are two views for it to cover), followed by each remaining view.
+    pub fn targets(&self) -> Vec<DrawTarget> {
+        let first = match self.stereo {
+            Some(_) if self.views.len() >= 2 => 2,
+            _ => 0,
+        };
+        let stereo = if first == 2 { Some(DrawTarget::Stereo) } else { None };
+        stereo.into_iter().chain((first..self.views.len()).map(DrawTarget::View)).collect()
+    }
+
+    /// The rendering statistics collected since the last call, which starts counting
+    /// a new frame.
+    pub fn take_stats(&mut self) -> FrameStats {
+        mem::replace(&mut self.stats, FrameStats::default())
+    }
+
+    /// Run a pass of drawing with the given name, adding the time spent encoding it to
+    /// `stats.passes`, along with its GPU time if a timer is given. `RenderGraph` times
+    /// its passes this way.
+    pub fn pass<'t, F>(&mut self, name: &str, mut timer: Option<&mut (GpuTimer<R, C> + 't)>, pass: F)
+        -> Result<(), Error>
+        where F: FnOnce(&mut DrawParams<R, C>) -> Result<(), Error>
+    {
+        if let Some(ref mut t) = timer {
+            t.begin(&mut self.encoder, name);
+        }
+        let start = Instant::now();
+        let result = pass(self);
+        let cpu = start.elapsed();
+        let gpu = match timer {
+            Some(t) => t.end(&mut self.encoder, name),
+            None => None,
+        };
+        self.stats.passes.push(PassTime {
+            name: name.to_owned(),
+            cpu: cpu,
+            gpu: gpu,
+        });
+        result
+    }
+
+    /// Clear the color and depth targets of every view. Integer color targets are
+    /// cleared to the color rounded towards zero.
+    pub fn clear(&mut self, color: [f32; 4], depth: f32) {
+        for v in &self.views {
+            // Clearing only depends on the channel type of the view, not on its surface type
+            let raw = v.target.color.clone();
+            match v.target.color_channel {
+                ChannelType::Int => {
+                    let c: RenderTargetView<R, (R8_G8_B8_A8, Int)> = Typed::new(raw);
+                    self.encoder.clear(&c, [color[0] as i32, color[1] as i32, color[2] as i32, color[3] as i32]);
+                },
+                ChannelType::Uint => {
+                    let c: RenderTargetView<R, (R8_G8_B8_A8, Uint)> = Typed::new(raw);
+                    self.encoder.clear(&c, [color[0] as u32, color[1] as u32, color[2] as u32, color[3] as u32]);
+                },
+                _ => {
+                    let c: HdrTargetRef<R> = Typed::new(raw);
+                    self.encoder.clear(&c, color);
+                },
+            }
+            let d: DepthRef<R> = Typed::new(v.target.depth.clone());
+            self.encoder.clear_depth(&d, depth);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{Buffer, ShaderResourceView, Sampler};
+use gfx::format::{R8_G8_B8_A8, Unorm, TextureFormat};
+use std::mem;
+
+use super::{StyleInputs, Style, RasterOptions, Features, ViewMode, TransformBlock, Painter, RawDepthTarget, EyeTarget, color_target, depth_target, BlockBuffer};
+use super::shaders::{BuildShader, Shaders};
+use ::mesh::{Primitive, VertNT};
+use ::{Error, Texture};
+
+/// The maximum number of user uniforms of a `CustomShader`
+pub const CUSTOM_UNIFORMS: usize = 16;
+/// The maximum number of user textures of a `CustomShader`
+pub const CUSTOM_TEXTURES: usize = 4;
+/// The name that compile errors give the body of a `CustomShader`
+const CUSTOM_BODY: &str = "custom body";
+
+gfx_defines!{
+    constant CustomParam {
+        value: [f32; 4] = "value",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<CustomParam> = "custom",
+        tex0: gfx::TextureSampler<[f32; 4]> = "custom_tex_0",
+        tex1: gfx::TextureSampler<[f32; 4]> = "custom_tex_1",
+        tex2: gfx::TextureSampler<[f32; 4]> = "custom_tex_2",
+        tex3: gfx::TextureSampler<[f32; 4]> = "custom_tex_3",
+        color: gfx::RawRenderTarget = color_target("f_color", None),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::LESS_EQUAL_WRITE),
+    }
+}
+
+/// The GLSL type of a user uniform
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UniformKind {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+impl UniformKind {
+    /// The components of a uniform slot that hold a value of this kind
+    fn swizzle(self) -> &'static str {
+        match self {
+            UniformKind::Float => ".x",
+            UniformKind::Vec2 => ".xy",
+            UniformKind::Vec3 => ".xyz",
+            UniformKind::Vec4 => "",
+        }
+    }
+}
+
+/// A value that can be given to a user uniform
+pub trait UniformValue: Copy {
+    /// The GLSL type of the value
+    fn kind() -> UniformKind;
+    /// The value, padded to four components
+    fn to_vec4(&self) -> [f32; 4];
+}
+
+impl UniformValue for f32 {
+    fn kind() -> UniformKind { UniformKind::Float }
+    fn to_vec4(&self) -> [f32; 4] { [*self, 0., 0., 0.] }
+}
+
+impl UniformValue for [f32; 2] {
+    fn kind() -> UniformKind { UniformKind::Vec2 }
+    fn to_vec4(&self) -> [f32; 4] { [self[0], self[1], 0., 0.] }
+}
+
+impl UniformValue for [f32; 3] {
+    fn kind() -> UniformKind { UniformKind::Vec3 }
+    fn to_vec4(&self) -> [f32; 4] { [self[0], self[1], self[2], 0.] }
+}
+
+impl UniformValue for [f32; 4] {
+    fn kind() -> UniformKind { UniformKind::Vec4 }
+    fn to_vec4(&self) -> [f32; 4] { *self }
+}
+
+/// Is the given name usable as a GLSL identifier?
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => (),
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// The shading of a `CustomStyle`: the body of a GLSL function returning the color of
+/// a fragment as a `vec4`. The body can use the world-space position, normal (not
+/// normalized) and texture coordinates `v_pos`, `v_norm` and `v_tex`, the `transform`
+/// uniforms (including `eye_pos`), and any uniforms and textures declared here. The
+/// result is a linear HDR color, which the `PostChain` tone maps, with alpha as opacity.
+#[derive(Clone, Debug)]
+pub struct CustomShader {
+    body: String,
+    uniforms: Vec<(String, UniformKind, [f32; 4])>,
+    textures: Vec<String>,
+}
+
+impl CustomShader {
+    /// Start a shader with the given function body, e.g.
+    /// `"return vec4(normalize(v_norm) * 0.5 + 0.5, 1.0);"`.
+    pub fn new<S: Into<String>>(body: S) -> CustomShader {
+        CustomShader {
+            body: body.into(),
+            uniforms: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    /// Declare a uniform with the given name and initial value. Its type is taken from
+    /// the value (`f32` is a `float`, `[f32; 3]` is a `vec3`, etc).
+    pub fn uniform<V: UniformValue>(mut self, name: &str, initial: V) -> CustomShader {
+        self.uniforms.push((name.to_owned(), V::kind(), initial.to_vec4()));
+        self
+    }
+
+    /// Declare a `sampler2D` with the given name. It is white until set.
+    pub fn texture(mut self, name: &str) -> CustomShader {
+        self.textures.push(name.to_owned());
+        self
+    }
+
+    /// The complete source of the fragment shader.
+    fn source(&self) -> Result<String, Error> {
+        ensure!(self.uniforms.len() <= CUSTOM_UNIFORMS,
+            "A custom shader can have at most {} uniforms", CUSTOM_UNIFORMS);
+        ensure!(self.textures.len() <= CUSTOM_TEXTURES,
+            "A custom shader can have at most {} textures", CUSTOM_TEXTURES);
+        let names: Vec<&String> = self.uniforms.iter().map(|u| &u.0).chain(&self.textures).collect();
+        for (i, name) in names.iter().enumerate() {
+            ensure!(is_identifier(name), "\"{}\" is not a valid uniform name", name);
+            ensure!(!names[..i].contains(name), "The uniform \"{}\" is declared twice", name);
+        }
+
+        let mut src = super::shaders::read_source("shaders/custom.f.glsl", include_str!("shaders/custom.f.glsl"));
+        src += "\n";
+        for (i, &(ref name, kind, _)) in self.uniforms.iter().enumerate() {
+            src += &format!("#define {} custom_params[{}].value{}\n", name, i, kind.swizzle());
+        }
+        for (i, name) in self.textures.iter().enumerate() {
+            src += &format!("uniform sampler2D custom_tex_{};\n#define {} custom_tex_{}\n", i, name, i);
+        }
+        // the body is included as a file of its own, so that compile errors quote its lines
+        src += &format!("\nvec4 shade() {{\n#include \"{}\"\n}}\n", CUSTOM_BODY);
+        Ok(src)
+    }
+
+    fn fragment(&self) -> Result<BuildShader, Error> {
+        Ok(super::shaders::generated("shaders/custom.f.glsl", self.source()?)
+            .file(CUSTOM_BODY, &self.body)
+            .define_to("CUSTOM_UNIFORMS", CUSTOM_UNIFORMS))
+    }
+}
+
+fn vertex() -> BuildShader {
+    static_file!("shaders/transform.v.glsl")
+        .define("NORM")
+        .define("TEX")
+}
+
+/// The configuration for custom rendering: the shader and the values of its uniforms
+/// and textures
+pub struct CustomInputs<R: Resources> {
+    shader: CustomShader,
+    shaders: Shaders<R>,
+    transform: Option<TransformBlock>,
+    transform_block: BlockBuffer<R, TransformBlock>,
+    params: [CustomParam; CUSTOM_UNIFORMS],
+    params_changed: bool,
+    params_block: Buffer<R, CustomParam>,
+    textures: Vec<(ShaderResourceView<R, [f32; 4]>, Sampler<R>)>,
+}
+
+impl<R: Resources> CustomInputs<R> {
+    /// Compile the given shader.
+    pub fn new<F: Factory<R>>(f: &mut F, shader: CustomShader) -> Result<CustomInputs<R>, Error> {
+        let shaders = shader_set!(f, vertex: vertex(), fragment: shader.fragment()?);
+        let mut params = [CustomParam { value: [0.; 4] }; CUSTOM_UNIFORMS];
+        for (p, u) in params.iter_mut().zip(&shader.uniforms) {
+            p.value = u.2;
+        }
+        let white = Texture::<R, (R8_G8_B8_A8, Unorm)>::uniform_value(f, [0xFF; 4])?.into_tuple();
+        Ok(CustomInputs {
+            shader: shader,
+            shaders: shaders,
+            transform: None,
+            transform_block: BlockBuffer::new(f),
+            params: params,
+            params_changed: true,
+            params_block: f.create_constant_buffer(CUSTOM_UNIFORMS),
+            textures: vec![white; CUSTOM_TEXTURES],
+        })
+    }
+
+    /// Sets the value of the uniform with the given name
+    pub fn set<V: UniformValue>(&mut self, name: &str, value: V) -> Result<(), Error> {
+        let i = self.shader.uniforms.iter().position(|u| u.0 == name)
+            .ok_or_else(|| format_err!("The custom shader has no uniform named \"{}\"", name))?;
+        let kind = self.shader.uniforms[i].1;
+        ensure!(kind == V::kind(), "The uniform \"{}\" is a {:?}, not a {:?}", name, kind, V::kind());
+        self.params[i].value = value.to_vec4();
+        self.params_changed = true;
+        Ok(())
+    }
+
+    /// Sets the texture with the given name
+    pub fn texture<T>(&mut self, name: &str, tex: &Texture<R, T>) -> Result<(), Error>
+        where T: TextureFormat<View = [f32; 4]>
+    {
+        let i = self.shader.textures.iter().position(|t| t == name)
+            .ok_or_else(|| format_err!("The custom shader has no texture named \"{}\"", name))?;
+        self.textures[i] = tex.clone().into_tuple();
+        Ok(())
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for CustomInputs<R> {
+    type Shaders = Shaders<R>;
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { self.shaders.set() }
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Shaders<R>, Error> {
+        Ok(shader_set!(f, vertex: vertex(), fragment: self.shader.fragment()?))
+    }
+    fn swap_shaders(&mut self, shaders: Shaders<R>) -> Shaders<R> {
+        mem::replace(&mut self.shaders, shaders)
+    }
+}
+
+/// Draws meshes with a user-defined fragment shader (see `CustomShader`). Painters
+/// in this style are created with `CustomStyle::painter`, since they need a shader.
+pub struct CustomStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> CustomStyle<R> {
+    /// Create a painter that draws with the given shader.
+    pub fn painter<F>(f: &mut F, shader: CustomShader) -> Result<Painter<R, CustomStyle<R>>, Error>
+        where F: Factory<R> + FactoryExt<R>
+    {
+        Ok(Painter::with_inputs(CustomInputs::new(f, shader)?, Default::default()))
+    }
+}
+
+impl<R: Resources> Style<R> for CustomStyle<R> {
+    type Vertex = VertNT;
+    type Inputs = CustomInputs<R>;
+    type Material = ();
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut CustomInputs<R>,
+        p: Primitive,
+        o: &RasterOptions,
+        _: Features,
+        mode: ViewMode,
+    ) -> Result<Self, Error> {
+        let stereo;
+        let shaders = match mode {
+            ViewMode::Single => &i.shaders,
+            ViewMode::Stereo => {
+                let v = vertex().define("STEREO");
+                let g = super::shaders::stereo_geometry(p)?.defines_from(&v);
+                stereo = shader_set!(f, vertex: v, geometry: g, fragment: i.shader.fragment()?.define("STEREO"));
+                &stereo
+            },
+        };
+        Ok(CustomStyle {
+            pso: shaders.pipeline_state(f, p, o.rasterizer(), pipeline_init!(pl, o))?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        _: &mut F,
+    ) -> Result<CustomInputs<R>, Error> {
+        Err(format_err!("Custom styles need a shader, so their painters are created with CustomStyle::painter"))
+    }
+
+    fn draw_raw<C>(
+        &self,
+        inputs: &mut CustomInputs<R>,
+        enc: &mut Encoder<R, C>,
+        target: &EyeTarget<R>,
+        slice: &Slice<R>,
+        buf: Buffer<R, Self::Vertex>,
+        _: &(),
+    )
+        -> Result<usize, Error>
+        where C: CommandBuffer<R>
+    {
+        let mut updates = 0;
+        if let Some(t) = inputs.transform.take() {
+            updates += inputs.transform_block.update(enc, &t);
+        }
+        if inputs.params_changed {
+            enc.update_buffer(&inputs.params_block, &inputs.params, 0)?;
+            updates += 1;
+            inputs.params_changed = false;
+        }
+        enc.draw(slice, &self.pso, &pl::Data {
+            color: target.color.clone(),
+            depth: target.depth.clone(),
+            verts: buf,
+            transform: inputs.transform_block.buffer().clone(),
+            params: inputs.params_block.clone(),
+            tex0: inputs.textures[0].clone(),
+            tex1: inputs.textures[1].clone(),
+            tex2: inputs.textures[2].clone(),
+            tex3: inputs.textures[3].clone(),
+        });
+        Ok(updates)
+    }
+}
+
+#[test]
+fn custom_source() {
+    let shader = CustomShader::new("return vec4(tint * texture(noise, v_tex).rgb, 1.0);")
+        .uniform("tint", [1f32, 0., 0.])
+        .uniform("speed", 2f32)
+        .texture("noise");
+    let src = shader.source().unwrap();
+    assert!(src.contains("#define tint custom_params[0].value.xyz\n"));
+    assert!(src.contains("#define speed custom_params[1].value.x\n"));
+    assert!(src.contains("#define noise custom_tex_0\n"));
+    assert!(src.ends_with("#include \"custom body\"\n}\n"));
+    let built = shader.fragment().unwrap().build().unwrap();
+    let body = built.find("\nreturn vec4(tint").unwrap();
+    let line = built[..body].rsplit('\n').next().unwrap();
+    assert!(line.starts_with("#line 1 ") && line != "#line 1 0");
+
+    assert!(CustomShader::new("").uniform("2d", 0f32).source().is_err());
+    assert!(CustomShader::new("").uniform("a", 0f32).texture("a").source().is_err());
+}
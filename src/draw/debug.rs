@@ -0,0 +1,409 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Slice};
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use gfx::memory::{Bind, Usage};
+use gfx::buffer::Role;
+use nalgebra::{self as na, Point3, Vector3, Isometry3};
+use std::cell::RefCell;
+use std::time::{Instant, Duration};
+use std::f32::consts::PI;
+
+use super::{DrawParams, EyeParams, FrameStats, Painter, SolidStyle};
+use ::mesh::{Mesh, Primitive, VertC};
+use ::vr::MappedController;
+use ::Error;
+
+/// The number of segments used to draw each circle of a sphere
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// The length of a millisecond in the bars of `DebugDraw::frame_stats`, in meters
+const STATS_MILLI_LENGTH: f32 = 0.01;
+
+/// How long a debug primitive stays visible
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Lifetime {
+    /// Visible for the given number of flushes (at least one)
+    Frames(u32),
+    /// Visible until the given number of seconds have passed
+    Seconds(f32),
+}
+
+/// The appearance of a debug primitive. A plain color (e.g. `[1., 0., 0.]`) converts
+/// into options that are depth tested and visible for a single frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DebugOptions {
+    /// The color of the lines
+    pub color: [f32; 3],
+    /// Hide the lines behind other objects, otherwise they are drawn over everything
+    pub depth_test: bool,
+    /// How long the lines stay visible
+    pub lifetime: Lifetime,
+}
+
+impl Default for DebugOptions {
+    fn default() -> DebugOptions {
+        DebugOptions {
+            color: [1., 1., 1.],
+            depth_test: true,
+            lifetime: Lifetime::Frames(1),
+        }
+    }
+}
+
+impl From<[f32; 3]> for DebugOptions {
+    fn from(color: [f32; 3]) -> DebugOptions {
+        DebugOptions {
+            color: color,
+            .. Default::default()
+        }
+    }
+}
+
+impl DebugOptions {
+    /// Draw over everything, without depth testing.
+    pub fn overlay(self) -> DebugOptions {
+        DebugOptions { depth_test: false, .. self }
+    }
+
+    /// Stay visible for the given number of frames.
+    pub fn frames(self, frames: u32) -> DebugOptions {
+        DebugOptions { lifetime: Lifetime::Frames(frames), .. self }
+    }
+
+    /// Stay visible for the given number of seconds.
+    pub fn seconds(self, seconds: f32) -> DebugOptions {
+        DebugOptions { lifetime: Lifetime::Seconds(seconds), .. self }
+    }
+}
+
+/// When a batch of lines should be removed
+#[derive(Copy, Clone, Debug)]
+enum Expiry {
+    Frames(u32),
+    At(Instant),
+}
+
+/// The lines of a single debug primitive
+struct Batch {
+    verts: Vec<VertC>,
+    depth_test: bool,
+    expiry: Expiry,
+}
+
+impl Batch {
+    fn new(opts: DebugOptions, points: Vec<Point3<f32>>, now: Instant) -> Batch {
+        Batch {
+            verts: points.into_iter()
+                .map(|p| VertC { pos: [p.x, p.y, p.z], color: opts.color })
+                .collect(),
+            depth_test: opts.depth_test,
+            expiry: match opts.lifetime {
+                Lifetime::Frames(n) => Expiry::Frames(n.max(1)),
+                Lifetime::Seconds(s) => {
+                    let s = s.max(0.);
+                    Expiry::At(now + Duration::new(s as u64, (s.fract() * 1e9) as u32))
+                },
+            },
+        }
+    }
+}
+
+/// Remove the batches that have expired by the given time.
+fn prune(batches: &mut Vec<Batch>, now: Instant) {
+    batches.retain(|b| match b.expiry {
+        Expiry::Frames(n) => n > 0,
+        Expiry::At(t) => t >= now,
+    });
+}
+
+/// Count a drawn frame against the lifetime of each batch.
+fn age(batches: &mut Vec<Batch>) {
+    for b in batches {
+        if let Expiry::Frames(ref mut n) = b.expiry {
+            *n = n.saturating_sub(1);
+        }
+    }
+}
+
+/// Two unit vectors perpendicular to the given direction and to each other.
+fn perpendiculars(dir: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let up = if dir.y.abs() > 0.9 * dir.norm() { Vector3::x() } else { Vector3::y() };
+    let a = dir.cross(&up).normalize();
+    let b = a.cross(dir).normalize();
+    (a, b)
+}
+
+/// Line segments tracing a circle around the given axis.
+fn circle(center: &Point3<f32>, axis: &Vector3<f32>, radius: f32) -> Vec<Point3<f32>> {
+    let (a, b) = perpendiculars(axis);
+    let point = |i: usize| {
+        let t = i as f32 / CIRCLE_SEGMENTS as f32 * 2. * PI;
+        center + (a * t.cos() + b * t.sin()) * radius
+    };
+    (0..CIRCLE_SEGMENTS).flat_map(|i| vec![point(i), point(i + 1)]).collect()
+}
+
+/// Line segments along the edges of a box, given its eight corners in binary order
+/// (bit 0 selects x, bit 1 selects y and bit 2 selects z).
+fn box_edges(corners: &[Point3<f32>; 8]) -> Vec<Point3<f32>> {
+    let mut lines = Vec::with_capacity(24);
+    for i in 0..8 {
+        for &bit in &[1, 2, 4] {
+            if i & bit == 0 {
+                lines.push(corners[i]);
+                lines.push(corners[i | bit]);
+            }
+        }
+    }
+    lines
+}
+
+/// Collects debugging shapes (lines, boxes, spheres, axes, frusta, rays and controller
+/// poses) from anywhere in an application, and draws them all at once with `flush`.
+/// Shapes are drawn as lines in solid colors, and stay visible for the lifetime given
+/// in their options.
+pub struct DebugDraw<R: Resources> {
+    painter: Painter<R, SolidStyle<R>>,
+    buf: Buffer<R, VertC>,
+    batches: RefCell<Vec<Batch>>,
+}
+
+impl<R: Resources> DebugDraw<R> {
+    /// Create a debug drawer that can draw up to 65536 line vertices per frame.
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<DebugDraw<R>, Error> {
+        DebugDraw::with_capacity(f, 65536)
+    }
+
+    /// Create a debug drawer that can draw up to the given number of line vertices
+    /// per frame. Any further lines are skipped.
+    pub fn with_capacity<F: Factory<R> + FactoryExt<R>>(f: &mut F, verts: usize)
+        -> Result<DebugDraw<R>, Error>
+    {
+        let mut painter = Painter::new(f)?;
+        painter.setup(f, Primitive::LineList)?;
+        Ok(DebugDraw {
+            painter: painter,
+            buf: f.create_buffer(verts, Role::Vertex, Usage::Dynamic, Bind::empty())?,
+            batches: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Compile the pipelines that drawing has needed since the last call, like those for
+    /// single-pass stereo (see `Painter::prepare`). This is cheap to call every frame.
+    pub fn prepare<F: Factory<R> + FactoryExt<R>>(&mut self, f: &mut F) -> Result<(), Error> {
+        self.painter.prepare(f)
+    }
+
+    /// Add line segments between each pair of points.
+    pub fn lines<O: Into<DebugOptions>>(&self, points: Vec<Point3<f32>>, opts: O) {
+        self.batches.borrow_mut().push(Batch::new(opts.into(), points, Instant::now()));
+    }
+
+    /// Add a line segment between two points.
+    pub fn line<O: Into<DebugOptions>>(&self, a: Point3<f32>, b: Point3<f32>, opts: O) {
+        self.lines(vec![a, b], opts);
+    }
+
+    /// Add an arrow from a point along the given vector.
+    pub fn ray<O: Into<DebugOptions>>(&self, origin: Point3<f32>, dir: Vector3<f32>, opts: O) {
+        let len = dir.norm();
+        if len <= ::std::f32::EPSILON { return }
+        let tip = origin + dir;
+        let back = dir * -0.1;
+        let (a, b) = perpendiculars(&dir);
+        let (a, b) = (a * 0.1 * len, b * 0.1 * len);
+        self.lines(vec![
+            origin, tip,
+            tip, tip + back + a,
+            tip, tip + back - a,
+            tip, tip + back + b,
+            tip, tip + back - b,
+        ], opts);
+    }
+
+    /// Add an axis-aligned box between two corners.
+    pub fn aabb<O: Into<DebugOptions>>(&self, min: Point3<f32>, max: Point3<f32>, opts: O) {
+        let center = na::center(&min, &max);
+        self.cuboid(Isometry3::new(center.coords, Vector3::zeros()), (max - min) / 2., opts);
+    }
+
+    /// Add a box with the given pose and half the size along each of its axes.
+    pub fn cuboid<O: Into<DebugOptions>>(&self, pose: Isometry3<f32>, half: Vector3<f32>, opts: O) {
+        let mut corners = [Point3::origin(); 8];
+        for (i, c) in corners.iter_mut().enumerate() {
+            let sign = |bit: usize| if i & bit == 0 { -1. } else { 1. };
+            *c = pose * Point3::new(sign(1) * half.x, sign(2) * half.y, sign(4) * half.z);
+        }
+        self.lines(box_edges(&corners), opts);
+    }
+
+    /// Add a sphere, drawn as a circle around each axis.
+    pub fn sphere<O: Into<DebugOptions>>(&self, center: Point3<f32>, radius: f32, opts: O) {
+        let mut lines = circle(&center, &Vector3::x(), radius);
+        lines.extend(circle(&center, &Vector3::y(), radius));
+        lines.extend(circle(&center, &Vector3::z(), radius));
+        self.lines(lines, opts);
+    }
+
+    /// Add the axes of a coordinate frame with the given pose and length, colored
+    /// red (x), green (y) and blue (z). Only the depth test and lifetime of the
+    /// options are used.
+    pub fn axes<O: Into<DebugOptions>>(&self, pose: Isometry3<f32>, size: f32, opts: O) {
+        let opts = opts.into();
+        let origin = pose * Point3::origin();
+        for (axis, color) in [Vector3::x(), Vector3::y(), Vector3::z()].iter().zip(&[
+            [1., 0., 0.],
+            [0., 1., 0.],
+            [0., 0., 1.],
+        ]) {
+            self.lines(vec![origin, origin + pose * (*axis * size)], DebugOptions { color: *color, .. opts });
+        }
+    }
+
+    /// Add the frustum that the given eye parameters can see.
+    pub fn frustum<O: Into<DebugOptions>>(&self, eye: &EyeParams, opts: O) {
+        let inverse = match (eye.proj * eye.view).try_inverse() {
+            Some(m) => m,
+            None => return,
+        };
+        let mut corners = [Point3::origin(); 8];
+        for (i, c) in corners.iter_mut().enumerate() {
+            let sign = |bit: usize| if i & bit == 0 { -1. } else { 1. };
+            *c = inverse * Point3::new(sign(1), sign(2), sign(4));
+        }
+        self.lines(box_edges(&corners), opts);
+    }
+
+    /// Add the pose of a controller, along with arrows showing its linear
+    /// velocity (m/s) and angular velocity (turns/s). Nothing is drawn if the
+    /// controller is not connected.
+    pub fn controller<O: Into<DebugOptions>>(&self, cont: &MappedController, opts: O) {
+        if !cont.connected { return }
+        let opts = opts.into();
+        let origin = cont.pose * Point3::origin();
+        self.axes(cont.pose, 0.1, opts);
+        self.ray(origin, cont.lin_vel, opts);
+        self.ray(origin, cont.ang_vel / (2. * PI), opts);
+    }
+
+    /// Add a bar chart of the pass times of a frame (see `FrameStats::passes`), e.g. to
+    /// watch them in the headset. The bars show the time spent encoding each pass, not
+    /// the time the GPU took to draw it. They grow along the x axis of the pose, one
+    /// centimeter per millisecond, and are stacked down its y axis, with the total at
+    /// the bottom. Passes timed by a `GpuTimer` get a second, yellow bar for their GPU
+    /// time just below.
+    pub fn frame_stats<O: Into<DebugOptions>>(&self, stats: &FrameStats, pose: Isometry3<f32>, opts: O) {
+        let opts = opts.into();
+        let bar = |row: f32, millis: f32| {
+            let y = -row * STATS_MILLI_LENGTH;
+            vec![pose * Point3::new(0., y, 0.), pose * Point3::new(millis * STATS_MILLI_LENGTH, y, 0.)]
+        };
+        let millis = |d: Duration| d.as_secs() as f32 * 1e3 + d.subsec_nanos() as f32 * 1e-6;
+        for (i, p) in stats.passes.iter().enumerate() {
+            self.lines(bar(i as f32, millis(p.cpu)), opts);
+            if let Some(gpu) = p.gpu {
+                self.lines(bar(i as f32 + 0.5, millis(gpu)), DebugOptions { color: [1., 1., 0.], .. opts });
+            }
+        }
+        let rows = stats.passes.len() as f32;
+        self.lines(bar(rows, millis(stats.pass_time())), DebugOptions { color: [1., 1., 1.], .. opts });
+    }
+
+    /// Remove all shapes, regardless of their lifetimes.
+    pub fn clear(&self) {
+        self.batches.borrow_mut().clear();
+    }
+
+    /// Attempt to draw all live shapes in a single batch, returning `Err` if something
+    /// goes wrong. Each call counts as a frame against the shapes' lifetimes.
+    pub fn try_flush<C>(&self, ctx: &mut DrawParams<R, C>) -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        let mut batches = self.batches.borrow_mut();
+        prune(&mut batches, Instant::now());
+
+        let mut verts: Vec<VertC> = batches.iter()
+            .filter(|b| b.depth_test)
+            .flat_map(|b| b.verts.iter().cloned())
+            .collect();
+        let tested = verts.len();
+        verts.extend(batches.iter()
+            .filter(|b| !b.depth_test)
+            .flat_map(|b| b.verts.iter().cloned()));
+        age(&mut batches);
+
+        if verts.len() > self.buf.len() {
+            warn!("Skipping {} debug line vertices over capacity", verts.len() - self.buf.len());
+            verts.truncate(self.buf.len());
+        }
+        if verts.is_empty() { return Ok(()) }
+        ctx.encoder.update_buffer(&self.buf, &verts, 0)
+            .map_err(|e| format_err!("Could not upload debug lines: {:?}", e))?;
+
+        let tested = tested.min(verts.len()) as u32;
+        let mesh = |start: u32, end: u32| Mesh {
+            slice: Slice {
+                start: start,
+                end: end,
+                base_vertex: 0,
+                instances: None,
+                buffer: gfx::IndexBuffer::Auto,
+            },
+            buf: self.buf.clone(),
+            prim: Primitive::LineList,
+            mat: (),
+        };
+        if tested > 0 {
+            self.painter.try_draw(ctx, na::one(), &mesh(0, tested))?;
+        }
+        if (tested as usize) < verts.len() {
+            self.painter.cfg(|c| c.depth_test(false));
+            let result = self.painter.try_draw(ctx, na::one(), &mesh(tested, verts.len() as u32));
+            self.painter.cfg(|c| c.depth_test(true));
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Draw all live shapes in a single batch, logging any errors.
+    pub fn flush<C>(&self, ctx: &mut DrawParams<R, C>)
+        where C: CommandBuffer<R>
+    {
+        if let Err(e) = self.try_flush(ctx) {
+            error!("{}", e);
+        }
+    }
+}
+
+#[test]
+fn lifetimes() {
+    let now = Instant::now();
+    let mut batches = vec![
+        Batch::new([1., 0., 0.].into(), vec![], now),
+        Batch::new(DebugOptions::default().frames(2), vec![], now),
+        Batch::new(DebugOptions::default().seconds(1.), vec![], now),
+    ];
+    prune(&mut batches, now);
+    assert_eq!(batches.len(), 3);
+    age(&mut batches);
+    prune(&mut batches, now);
+    assert_eq!(batches.len(), 2);
+    age(&mut batches);
+    prune(&mut batches, now + Duration::from_millis(500));
+    assert_eq!(batches.len(), 1);
+    prune(&mut batches, now + Duration::from_millis(1500));
+    assert!(batches.is_empty());
+}
+
+#[test]
+fn box_outline() {
+    let mut corners = [Point3::origin(); 8];
+    for (i, c) in corners.iter_mut().enumerate() {
+        *c = Point3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32);
+    }
+    let lines = box_edges(&corners);
+    assert_eq!(lines.len(), 24);
+    for pair in lines.chunks(2) {
+        assert!(relative_eq!((pair[1] - pair[0]).norm(), 1.));
+    }
+}
@@ -0,0 +1,441 @@
+use gfx::{Resources, CommandBuffer, Factory};
+use gfx::traits::FactoryExt;
+use gfx::handle::DepthStencilView;
+
+use super::{GpuTimer, DrawParams, EyeParams, Painter, UberStyle, RenderQueue, PostChain, HdrTarget, RenderTexture};
+use ::{Error, ShadowDepthFormat, TargetRef, Texture};
+
+/// A target that a `RenderGraph` allocates for its passes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TargetDesc {
+    /// HDR color and depth targets for drawing a scene (see `HdrTarget`)
+    Hdr {
+        width: u16,
+        height: u16,
+        eyes: u16,
+        samples: u8,
+    },
+    /// A depth buffer that can be sampled, e.g. a shadow map
+    Shadow {
+        width: u16,
+        height: u16,
+    },
+    /// An offscreen camera target, post-processed into a texture (see `RenderTexture`)
+    Texture {
+        width: u16,
+        height: u16,
+        samples: u8,
+    },
+}
+
+/// A depth buffer that can be sampled, allocated for `TargetDesc::Shadow`
+pub struct ShadowTarget<R: Resources> {
+    /// The depth target to draw into
+    pub depth: DepthStencilView<R, ShadowDepthFormat>,
+    /// The drawn depth, for sampling
+    pub texture: Texture<R, ShadowDepthFormat>,
+}
+
+enum Transient<R: Resources> {
+    Hdr(HdrTarget<R>),
+    Shadow(ShadowTarget<R>),
+    Texture(RenderTexture<R>),
+}
+
+impl<R: Resources> Transient<R> {
+    fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F, desc: TargetDesc) -> Result<Transient<R>, Error> {
+        Ok(match desc {
+            TargetDesc::Hdr { width, height, eyes, samples } =>
+                Transient::Hdr(HdrTarget::new_multisampled(f, width, height, eyes, samples)?),
+            TargetDesc::Shadow { width, height } => {
+                let (depth, texture) = super::uber::shadow_texture(f, width, height)?;
+                Transient::Shadow(ShadowTarget {
+                    depth: depth,
+                    texture: texture,
+                })
+            },
+            TargetDesc::Texture { width, height, samples } =>
+                Transient::Texture(RenderTexture::new_multisampled(f, width, height, samples)?),
+        })
+    }
+}
+
+/// The targets allocated by a `RenderGraph`, which its passes look up by name. Targets
+/// may share their allocation with others (see `GraphBuilder::target`).
+pub struct GraphTargets<R: Resources> {
+    names: Vec<(String, usize)>,
+    targets: Vec<Transient<R>>,
+}
+
+impl<R: Resources> GraphTargets<R> {
+    fn find(&mut self, name: &str) -> Result<&mut Transient<R>, Error> {
+        let i = self.names.iter()
+            .find(|t| t.0 == name)
+            .map(|t| t.1)
+            .ok_or_else(|| format_err!("The render graph has no target named \"{}\"", name))?;
+        Ok(&mut self.targets[i])
+    }
+
+    /// The HDR target with the given name.
+    pub fn hdr(&mut self, name: &str) -> Result<&mut HdrTarget<R>, Error> {
+        match self.find(name)? {
+            &mut Transient::Hdr(ref mut t) => Ok(t),
+            _ => bail!("The render graph target \"{}\" is not an HDR target", name),
+        }
+    }
+
+    /// The shadow target with the given name.
+    pub fn shadow(&mut self, name: &str) -> Result<&mut ShadowTarget<R>, Error> {
+        match self.find(name)? {
+            &mut Transient::Shadow(ref mut t) => Ok(t),
+            _ => bail!("The render graph target \"{}\" is not a shadow target", name),
+        }
+    }
+
+    /// The render texture with the given name.
+    pub fn texture(&mut self, name: &str) -> Result<&mut RenderTexture<R>, Error> {
+        match self.find(name)? {
+            &mut Transient::Texture(ref mut t) => Ok(t),
+            _ => bail!("The render graph target \"{}\" is not a render texture", name),
+        }
+    }
+}
+
+/// A pass declared in a graph, with the names of the targets it reads and writes
+struct PassDecl {
+    name: String,
+    reads: Vec<String>,
+    writes: Vec<String>,
+}
+
+/// Order passes so that each pass runs after the passes that write the targets it reads,
+/// and passes that write the same target run in the order they were declared. Among
+/// passes that are free to run, the earliest declared runs first.
+fn order(passes: &[PassDecl]) -> Result<Vec<usize>, Error> {
+    let after = |b: usize, a: usize| {
+        a != b && passes[a].writes.iter().any(|t| {
+            passes[b].reads.contains(t) || (a < b && passes[b].writes.contains(t))
+        })
+    };
+    let mut order: Vec<usize> = Vec::with_capacity(passes.len());
+    while order.len() < passes.len() {
+        let next = (0..passes.len())
+            .filter(|b| !order.contains(b))
+            .find(|&b| (0..passes.len()).all(|a| order.contains(&a) || !after(b, a)));
+        match next {
+            Some(b) => order.push(b),
+            None => {
+                let left: Vec<_> = (0..passes.len())
+                    .filter(|b| !order.contains(b))
+                    .map(|b| passes[b].name.as_str())
+                    .collect();
+                bail!("The render passes {} depend on each other", left.join(", "));
+            },
+        }
+    }
+    Ok(order)
+}
+
+/// Assign the targets of a graph to allocations, so that targets of the same description
+/// share one if the passes that use them do not overlap in the given order of the passes.
+/// Targets that no pass uses get their own. Returns the description of each allocation,
+/// and the allocation of each target.
+fn alias(targets: &[(String, TargetDesc)], passes: &[PassDecl], order: &[usize]) -> (Vec<TargetDesc>, Vec<usize>) {
+    // the first and last positions in the order of the passes using each target
+    let live: Vec<Option<(usize, usize)>> = targets.iter()
+        .map(|t| {
+            let used: Vec<usize> = order.iter()
+                .enumerate()
+                .filter(|&(_, &p)| passes[p].reads.contains(&t.0) || passes[p].writes.contains(&t.0))
+                .map(|(i, _)| i)
+                .collect();
+            match (used.first(), used.last()) {
+                (Some(&first), Some(&last)) => Some((first, last)),
+                _ => None,
+            }
+        })
+        .collect();
+    let mut by_first: Vec<usize> = (0..targets.len()).collect();
+    by_first.sort_by_key(|&t| live[t].map(|l| l.0));
+    // the description of each allocation and the last position it is used at
+    let mut allocs: Vec<(TargetDesc, Option<usize>)> = Vec::new();
+    let mut assigned = vec![0; targets.len()];
+    for t in by_first {
+        let desc = targets[t].1;
+        let free = live[t].and_then(|(first, _)| allocs.iter()
+            .position(|a| a.0 == desc && a.1.map_or(false, |last| last < first)));
+        let i = match free {
+            Some(i) => i,
+            None => {
+                allocs.push((desc, None));
+                allocs.len() - 1
+            },
+        };
+        allocs[i].1 = live[t].map(|l| l.1);
+        assigned[t] = i;
+    }
+    (allocs.into_iter().map(|a| a.0).collect(), assigned)
+}
+
+/// Declares the passes of a `RenderGraph` and the targets they use.
+pub struct GraphBuilder {
+    passes: Vec<PassDecl>,
+    targets: Vec<(String, TargetDesc)>,
+}
+
+impl GraphBuilder {
+    /// Declare a target that the graph allocates. Targets are transient: their contents
+    /// only last from the first to the last pass that uses them in a frame, since targets
+    /// of the same description share their allocation when the passes that use them do
+    /// not overlap. Targets that no pass uses are not shared.
+    pub fn target(mut self, name: &str, desc: TargetDesc) -> GraphBuilder {
+        self.targets.push((name.to_owned(), desc));
+        self
+    }
+
+    /// Declare a pass with the names of the targets it reads and writes. Targets that are
+    /// not allocated by the graph (e.g. the views of `DrawParams`) only order the passes.
+    pub fn pass(mut self, name: &str, reads: &[&str], writes: &[&str]) -> GraphBuilder {
+        self.passes.push(PassDecl {
+            name: name.to_owned(),
+            reads: reads.iter().map(|&t| t.to_owned()).collect(),
+            writes: writes.iter().map(|&t| t.to_owned()).collect(),
+        });
+        self
+    }
+
+    /// Order the passes and allocate the targets.
+    pub fn build<R, F>(self, f: &mut F) -> Result<RenderGraph<R>, Error>
+        where R: Resources, F: Factory<R> + FactoryExt<R>
+    {
+        for (i, p) in self.passes.iter().enumerate() {
+            ensure!(!self.passes[..i].iter().any(|q| q.name == p.name), "The render pass \"{}\" is declared twice", p.name);
+        }
+        let order = order(&self.passes)?;
+        let (allocs, assigned) = alias(&self.targets, &self.passes, &order);
+        let mut targets = Vec::with_capacity(allocs.len());
+        for desc in allocs {
+            targets.push(Transient::new(f, desc)?);
+        }
+        Ok(RenderGraph {
+            passes: self.passes,
+            order: order,
+            targets: GraphTargets {
+                names: self.targets.into_iter().map(|t| t.0).zip(assigned).collect(),
+                targets: targets,
+            },
+        })
+    }
+}
+
+/// The work of a pass in a frame of a `RenderGraph`. This is implemented for closures
+/// taking the draw parameters and the graph's targets, and for the built-in passes.
+pub trait GraphPass<R: Resources, C: CommandBuffer<R>> {
+    fn run(&mut self, ctx: &mut DrawParams<R, C>, targets: &mut GraphTargets<R>) -> Result<(), Error>;
+}
+
+impl<R, C, F> GraphPass<R, C> for F
+    where R: Resources, C: CommandBuffer<R>, F: FnMut(&mut DrawParams<R, C>, &mut GraphTargets<R>) -> Result<(), Error>
+{
+    fn run(&mut self, ctx: &mut DrawParams<R, C>, targets: &mut GraphTargets<R>) -> Result<(), Error> {
+        self(ctx, targets)
+    }
+}
+
+/// Clears the color and depth targets of every view.
+pub struct ClearPass {
+    pub color: [f32; 4],
+    pub depth: f32,
+}
+
+impl<R: Resources, C: CommandBuffer<R>> GraphPass<R, C> for ClearPass {
+    fn run(&mut self, ctx: &mut DrawParams<R, C>, _: &mut GraphTargets<R>) -> Result<(), Error> {
+        ctx.clear(self.color, self.depth);
+        Ok(())
+    }
+}
+
+/// Draws the environment of an `UberStyle` painter as the background (see `Painter::clear_env`).
+pub struct EnvPass<'a, R: Resources + 'a>(pub &'a Painter<R, UberStyle<R>>);
+
+impl<'a, R: Resources, C: CommandBuffer<R>> GraphPass<R, C> for EnvPass<'a, R> {
+    fn run(&mut self, ctx: &mut DrawParams<R, C>, _: &mut GraphTargets<R>) -> Result<(), Error> {
+        self.0.clear_env(ctx);
+        Ok(())
+    }
+}
+
+/// Submits a render queue (see `RenderQueue`), which can hold draws of any painters.
+pub struct QueuePass<'a, R: Resources, C: CommandBuffer<R>>(pub RenderQueue<'a, R, C>);
+
+impl<'a, R: Resources, C: CommandBuffer<R>> GraphPass<R, C> for QueuePass<'a, R, C> {
+    fn run(&mut self, ctx: &mut DrawParams<R, C>, _: &mut GraphTargets<R>) -> Result<(), Error> {
+        self.0.try_submit(ctx).map(|_| ())
+    }
+}
+
+/// Post-processes the eyes of an HDR target of the graph into a display target (see
+/// `PostChain::apply`), using the eye parameters of the first views.
+pub struct PostPass<'a, R: Resources + 'a> {
+    pub post: &'a mut PostChain<R>,
+    /// The name of the HDR target (see `GraphTargets::hdr`)
+    pub source: &'a str,
+    pub target: &'a TargetRef<R>,
+}
+
+impl<'a, R: Resources, C: CommandBuffer<R>> GraphPass<R, C> for PostPass<'a, R> {
+    fn run(&mut self, ctx: &mut DrawParams<R, C>, targets: &mut GraphTargets<R>) -> Result<(), Error> {
+        let source = targets.hdr(self.source)?;
+        let eyes: Vec<&EyeParams> = ctx.views.iter()
+            .take(source.eyes.len())
+            .map(|v| &v.eye)
+            .collect();
+        self.post.apply(&mut ctx.encoder, source, self.target, &eyes)
+    }
+}
+
+/// Runs the passes of a frame in a fixed order, worked out from the targets that each
+/// pass reads and writes, and owns the transient targets of the passes. The graph is built
+/// once (see `RenderGraph::builder`), and the work of each pass is given each frame, so
+/// that passes can borrow the painters and meshes they draw:
+///
+/// ```ignore
+/// let mut graph = RenderGraph::builder()
+///     .target("scene", TargetDesc::Hdr { width: 1280, height: 720, eyes: 1, samples: 4 })
+///     .pass("post", &["scene"], &["display"])
+///     .pass("opaque", &[], &["scene"])
+///     .build(&mut factory)?;
+///
+/// let mut frame = graph.frame();
+/// frame.pass("opaque", QueuePass(queue));
+/// frame.pass("post", PostPass { post: &mut post, source: "scene", target: &display });
+/// frame.run(&mut ctx)?;
+/// ```
+pub struct RenderGraph<R: Resources> {
+    passes: Vec<PassDecl>,
+    order: Vec<usize>,
+    targets: GraphTargets<R>,
+}
+
+impl<R: Resources> RenderGraph<R> {
+    /// Start declaring a graph.
+    pub fn builder() -> GraphBuilder {
+        GraphBuilder {
+            passes: Vec::new(),
+            targets: Vec::new(),
+        }
+    }
+
+    /// The names of the passes in the order they run.
+    pub fn order(&self) -> Vec<&str> {
+        self.order.iter().map(|&i| self.passes[i].name.as_str()).collect()
+    }
+
+    /// The targets allocated by the graph.
+    pub fn targets(&mut self) -> &mut GraphTargets<R> {
+        &mut self.targets
+    }
+
+    /// Start a frame, to which the work of each pass is given.
+    pub fn frame<'a, C: CommandBuffer<R>>(&'a mut self) -> Frame<'a, R, C> {
+        Frame {
+            graph: self,
+            work: Vec::new(),
+            timer: None,
+        }
+    }
+}
+
+/// A frame of a `RenderGraph`, collecting the work of its passes.
+pub struct Frame<'a, R: Resources + 'a, C: CommandBuffer<R>> {
+    graph: &'a mut RenderGraph<R>,
+    work: Vec<(String, Box<GraphPass<R, C> + 'a>)>,
+    timer: Option<&'a mut GpuTimer<R, C>>,
+}
+
+impl<'a, R: Resources, C: CommandBuffer<R>> Frame<'a, R, C> {
+    /// Give the work of the pass with the given name.
+    pub fn pass<P: GraphPass<R, C> + 'a>(&mut self, name: &str, work: P) {
+        self.work.push((name.to_owned(), Box::new(work)));
+    }
+
+    /// Time the passes on the GPU with the given timer, as well as on the CPU.
+    pub fn timer(&mut self, timer: &'a mut GpuTimer<R, C>) {
+        self.timer = Some(timer);
+    }
+
+    /// Run the passes in order, timing each in `DrawParams::stats`. Every declared pass
+    /// must have been given its work.
+    pub fn run(mut self, ctx: &mut DrawParams<R, C>) -> Result<(), Error> {
+        let graph = self.graph;
+        let mut timer = self.timer;
+        for w in &self.work {
+            ensure!(graph.passes.iter().any(|p| p.name == w.0), "The render graph has no pass named \"{}\"", w.0);
+        }
+        for &i in &graph.order {
+            let name = &graph.passes[i].name;
+            let work = self.work.iter_mut()
+                .find(|w| w.0 == *name)
+                .ok_or_else(|| format_err!("The render pass \"{}\" was not given any work", name))?;
+            let targets = &mut graph.targets;
+            ctx.pass(name, timer.as_mut().map(|t| &mut **t), |ctx| work.1.run(ctx, targets))
+                .map_err(|e| format_err!("Render pass \"{}\" failed: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn pass_order() {
+    let pass = |name: &str, reads: &[&str], writes: &[&str]| PassDecl {
+        name: name.to_owned(),
+        reads: reads.iter().map(|&t| t.to_owned()).collect(),
+        writes: writes.iter().map(|&t| t.to_owned()).collect(),
+    };
+    let passes = vec![
+        pass("post", &["scene"], &["display"]),
+        pass("opaque", &["shadow"], &["scene"]),
+        pass("transparent", &[], &["scene"]),
+        pass("shadow", &[], &["shadow"]),
+    ];
+    assert_eq!(order(&passes).unwrap(), vec![3, 1, 2, 0]);
+
+    let cycle = vec![
+        pass("a", &["y"], &["x"]),
+        pass("b", &["x"], &["y"]),
+    ];
+    assert!(order(&cycle).is_err());
+}
+
+#[test]
+fn target_aliasing() {
+    let pass = |name: &str, reads: &[&str], writes: &[&str]| PassDecl {
+        name: name.to_owned(),
+        reads: reads.iter().map(|&t| t.to_owned()).collect(),
+        writes: writes.iter().map(|&t| t.to_owned()).collect(),
+    };
+    let passes = vec![
+        pass("shadow", &[], &["shadow"]),
+        pass("mirror", &["shadow"], &["mirror"]),
+        pass("scene", &["shadow", "mirror"], &["scene"]),
+        pass("post", &["scene"], &["display"]),
+        pass("overlay", &[], &["overlay"]),
+        pass("compose", &["overlay"], &["display"]),
+    ];
+    let hdr = TargetDesc::Hdr { width: 64, height: 64, eyes: 1, samples: 1 };
+    let targets = vec![
+        ("shadow".to_owned(), TargetDesc::Shadow { width: 64, height: 64 }),
+        ("mirror".to_owned(), hdr),
+        ("scene".to_owned(), hdr),
+        ("overlay".to_owned(), hdr),
+        ("unused".to_owned(), hdr),
+    ];
+    let order = order(&passes).unwrap();
+    let (allocs, assigned) = alias(&targets, &passes, &order);
+    assert_eq!(allocs.len(), 4);
+    // the mirror is read by the scene pass, but is free for the overlay
+    assert!(assigned[1] != assigned[2]);
+    assert_eq!(assigned[1], assigned[3]);
+    assert!(assigned[0] != assigned[1] && assigned[0] != assigned[2]);
+    assert!(!assigned[..4].contains(&assigned[4]));
+}
@@ -0,0 +1,169 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use std::mem;
+
+use super::{StyleInputs, Style, RasterOptions, Features, ViewMode, TransformBlock, RawDepthTarget, EyeTarget, PREMULTIPLIED, color_target, depth_target, BlockBuffer};
+use super::shaders::Shaders;
+use ::mesh::{Primitive, VertC};
+use ::{Error, FlightError};
+
+gfx_defines!{
+    constant LineBlock {
+        viewport: [f32; 2] = "viewport",
+        width: f32 = "width",
+        pixels: i32 = "pixels",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertC> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        line: gfx::ConstantBuffer<LineBlock> = "line",
+        color: gfx::RawRenderTarget = color_target("f_color", Some(PREMULTIPLIED)),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::LESS_EQUAL_TEST),
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("GEOMETRY")
+        .define("COLOR"),
+    geometry: static_file!("shaders/line.g.glsl"),
+    fragment: static_file!("shaders/line.f.glsl"),
+});
+
+shader!(stereo_shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("STEREO")
+        .define("COLOR"),
+    geometry: static_file!("shaders/line.g.glsl")
+        .define("STEREO"),
+    fragment: static_file!("shaders/line.f.glsl"),
+});
+
+/// The width of lines drawn by `LineStyle`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LineWidth {
+    /// A width in world units, so that lines get thinner with distance
+    World(f32),
+    /// A width in pixels, regardless of distance
+    Pixels(f32),
+}
+
+/// The configuration for wide line rendering
+pub struct LineInputs<R: Resources> {
+    shaders: Shaders<R>,
+    transform: Option<TransformBlock>,
+    transform_block: BlockBuffer<R, TransformBlock>,
+    width: LineWidth,
+    line_block: Buffer<R, LineBlock>,
+}
+
+impl<R: Resources> LineInputs<R> {
+    /// Sets the width of the lines (2 pixels by default)
+    pub fn width(&mut self, width: LineWidth) {
+        self.width = width;
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for LineInputs<R> {
+    type Shaders = Shaders<R>;
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { self.shaders.set() }
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Shaders<R>, Error> {
+        shader(f)
+    }
+    fn swap_shaders(&mut self, shaders: Shaders<R>) -> Shaders<R> {
+        mem::replace(&mut self.shaders, shaders)
+    }
+}
+
+/// Draws anti-aliased lines of any width in solid colors (without lighting) using the
+/// per-vertex color attribute. Each segment is expanded into a quad facing the camera.
+/// Lines are blended over the scene and do not write depth, so they should be drawn
+/// after any opaque objects.
+pub struct LineStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for LineStyle<R> {
+    type Vertex = VertC;
+    type Inputs = LineInputs<R>;
+    type Material = ();
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut LineInputs<R>,
+        p: Primitive,
+        o: &RasterOptions,
+        _: Features,
+        mode: ViewMode,
+    ) -> Result<Self, Error> {
+        match p {
+            Primitive::LineList | Primitive::LineStrip => (),
+            _ => Err(FlightError::InvalidPrimitive { given: p })?,
+        }
+        let stereo;
+        let shaders = match mode {
+            ViewMode::Single => &i.shaders,
+            ViewMode::Stereo => {
+                stereo = stereo_shader(f)?;
+                &stereo
+            },
+        };
+        Ok(LineStyle {
+            pso: shaders.pipeline_state(f, p, o.rasterizer(), pipeline_init!(pl, o))?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<LineInputs<R>, Error> {
+        Ok(LineInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: BlockBuffer::new(f),
+            width: LineWidth::Pixels(2.),
+            line_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn draw_raw<C>(
+        &self,
+        inputs: &mut LineInputs<R>,
+        enc: &mut Encoder<R, C>,
+        target: &EyeTarget<R>,
+        slice: &Slice<R>,
+        buf: Buffer<R, Self::Vertex>,
+        _: &(),
+    )
+        -> Result<usize, Error>
+        where C: CommandBuffer<R>
+    {
+        let mut updates = 0;
+        if let Some(t) = inputs.transform.take() {
+            updates += inputs.transform_block.update(enc, &t);
+        }
+        // Views are always drawn over their whole target
+        let (w, h, ..) = target.color.get_dimensions();
+        let (width, pixels) = match inputs.width {
+            LineWidth::World(x) => (x, 0),
+            LineWidth::Pixels(x) => (x, 1),
+        };
+        enc.update_constant_buffer(&inputs.line_block, &LineBlock {
+            viewport: [w as f32, h as f32],
+            width: width,
+            pixels: pixels,
+        });
+        updates += 1;
+        enc.draw(slice, &self.pso, &pl::Data {
+            color: target.color.clone(),
+            depth: target.depth.clone(),
+            verts: buf,
+            transform: inputs.transform_block.buffer().clone(),
+            line: inputs.line_block.clone(),
+        });
+        Ok(updates)
+    }
+}
@@ -1,63 +1,480 @@
-use gfx::{Resources, Encoder, Primitive, Rect, CommandBuffer, Slice, ShaderSet, Factory};
-use gfx::handle::Buffer;
+use gfx::{Resources, Encoder, Primitive, CommandBuffer, Slice, ShaderSet, Factory};
+use gfx::handle::{Buffer, ShaderResourceView, Sampler};
 use gfx::traits::FactoryExt;
-use gfx::state::Rasterizer;
-use nalgebra::{Transform3};
-use fnv::FnvHashMap;
+use gfx::state::{Rasterizer, MultiSample, CullFace, FrontFace, RasterMethod, Offset, Depth, ColorMask, Blend, BlendChannel, Equation, Factor, BlendValue};
+use gfx::format::{R8, R8_G8_B8_A8, Unorm, Srgb, TextureFormat, Format};
+use nalgebra::{Transform3, Point3};
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
 use failure::Fail;
 use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ops::BitOr;
 
-use ::{DepthRef, TargetRef, Error, FlightError, NativeRepr};
+use ::{Error, FlightError, NativeRepr, Texture};
 use ::mesh::{Mesh, Vertex};
 
 #[macro_use]
 mod shaders;
+pub use self::shaders::{set_shader_dir, shader_dir, SHADER_DIR_VAR};
 mod context;
 pub use self::context::*;
+mod stats;
+pub use self::stats::{FrameStats, PassTime, PipelineKey, GpuTimer};
+#[macro_use]
+mod target;
+pub use self::target::{TargetFormats, RawDepthTarget};
 
 mod solid;
 pub use self::solid::{SolidStyle, SolidInputs};
 
+mod line;
+pub use self::line::{LineStyle, LineInputs, LineWidth};
+
 mod unishade;
 pub use self::unishade::{UnishadeStyle, UnishadeInputs};
 
+mod textured;
+pub use self::textured::{TexturedStyle, TexturedMaterial, TexturedInputs};
+
+mod vertex_color;
+pub use self::vertex_color::{VertexColorStyle, VertexColorInputs};
+
+mod custom;
+pub use self::custom::{CustomStyle, CustomShader, CustomInputs, UniformKind, UniformValue, CUSTOM_UNIFORMS, CUSTOM_TEXTURES};
+
 mod pbr;
 pub use self::pbr::{PbrStyle, PbrMaterial, PbrInputs, LIGHT_COUNT};
 
 mod uber;
 pub use self::uber::{UberStyle, UberMaterial, UberInputs, UberEnv};
 
+mod post;
+pub use self::post::{PostChain, HdrTarget, ToneMap, AutoExposure, Metering};
+
+mod offscreen;
+pub use self::offscreen::{RenderTexture, RenderTextureFormat};
+
+mod spectator;
+pub use self::spectator::{Spectator, SpectatorMode};
+
+mod capture;
+pub use self::capture::{FrameCapture, CapturedFrame};
+
+mod queue;
+pub use self::queue::{RenderQueue, Pass, QueueStats};
+
+mod graph;
+pub use self::graph::{RenderGraph, GraphBuilder, GraphTargets, GraphPass, Frame, TargetDesc, ShadowTarget, ClearPass, EnvPass, QueuePass, PostPass};
+
+mod debug;
+pub use self::debug::{DebugDraw, DebugOptions, Lifetime};
+
+/// The default rasterizer state for styles. Multisampling is enabled so that the same
+/// pipelines draw anti-aliased into multisampled targets (it has no effect otherwise).
+fn fill_rasterizer() -> Rasterizer {
+    Rasterizer {
+        samples: Some(MultiSample),
+        .. Rasterizer::new_fill()
+    }
+}
+
+/// Blends colors that are premultiplied by their alpha (e.g. by how much of a pixel
+/// they cover) over the target.
+const PREMULTIPLIED: Blend = Blend {
+    color: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::One,
+        destination: Factor::OneMinus(BlendValue::SourceAlpha),
+    },
+    alpha: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::One,
+        destination: Factor::OneMinus(BlendValue::SourceAlpha),
+    },
+};
+
+/// The initializer of a `gfx::RawRenderTarget` in the default format, for declaring the
+/// pipelines of styles. `RasterOptions::color_target` changes the format.
+fn color_target(name: &str, blend: Option<Blend>) -> (&str, Format, ColorMask, Option<Blend>) {
+    (name, TargetFormats::default().color, ColorMask::all(), blend)
+}
+
+/// The initializer of a `RawDepthTarget` in the default format, for declaring the
+/// pipelines of styles. `RasterOptions::depth_target` changes the format.
+fn depth_target(depth: Depth) -> (Format, Depth) {
+    (TargetFormats::default().depth, depth)
+}
+
+/// Rasterizer, depth state and target formats for the pipelines of a painter. The defaults
+/// match the pipelines built by `Painter::setup`: filled polygons, no culling, no depth bias,
+/// the depth state of the style, and the formats of `HdrTarget`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RasterOptions {
+    /// Which faces are culled
+    pub cull: CullFace,
+    /// Which winding order makes a face the front face
+    pub front_face: FrontFace,
+    /// Draw polygons filled, or as wireframe with `RasterMethod::Line`
+    pub method: RasterMethod,
+    /// Depth bias (slope factor and units), e.g. to draw a wireframe over its own mesh
+    pub offset: Option<Offset>,
+    /// The depth test and write state (see `gfx::preset::depth`), or `None` to use
+    /// the style's own
+    pub depth: Option<Depth>,
+    /// The formats of the targets that meshes are drawn into
+    pub formats: TargetFormats,
+}
+
+impl Default for RasterOptions {
+    fn default() -> RasterOptions {
+        let r = fill_rasterizer();
+        RasterOptions {
+            cull: r.cull_face,
+            front_face: r.front_face,
+            method: r.method,
+            offset: r.offset,
+            depth: None,
+            formats: Default::default(),
+        }
+    }
+}
+
+impl RasterOptions {
+    /// Options for drawing the wireframe of meshes on top of the same meshes drawn filled.
+    pub fn wireframe() -> RasterOptions {
+        RasterOptions {
+            method: RasterMethod::Line(1),
+            offset: Some(Offset(-1, -1)),
+            depth: Some(::gfx::preset::depth::LESS_EQUAL_TEST),
+            .. Default::default()
+        }
+    }
+
+    /// Options that cull back faces.
+    pub fn cull_back() -> RasterOptions {
+        RasterOptions {
+            cull: CullFace::Back,
+            .. Default::default()
+        }
+    }
+
+    /// The given color target of a style's pipeline, in the color format of these options.
+    pub fn color_target<'a>(&self, init: (&'a str, Format, ColorMask, Option<Blend>))
+        -> (&'a str, Format, ColorMask, Option<Blend>)
+    {
+        (init.0, self.formats.color, init.2, init.3)
+    }
+
+    /// The given depth target of a style's pipeline, in the depth format and with the
+    /// depth state (if any) of these options.
+    pub fn depth_target(&self, init: (Format, Depth)) -> (Format, Depth) {
+        (self.formats.depth, self.depth.unwrap_or(init.1))
+    }
+
+    /// The rasterizer state of these options.
+    pub fn rasterizer(&self) -> Rasterizer {
+        Rasterizer {
+            front_face: self.front_face,
+            cull_face: self.cull,
+            method: self.method,
+            offset: self.offset,
+            .. fill_rasterizer()
+        }
+    }
+}
+
+/// Pick the given material map, falling back on a default if it is missing.
+fn or_default<R, T>(map: &Option<Texture<R, T>>, default: &Texture<R, T>)
+    -> (ShaderResourceView<R, T::View>, Sampler<R>)
+    where R: Resources, T: TextureFormat
+{
+    map.as_ref().unwrap_or(default).clone().into_tuple()
+}
+
+/// A set of optional shader features of a style (see `Style::features`). Bit `i` selects
+/// the style's feature `i`, which is `#define`d in the shaders of the variant.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Features(pub u32);
+
+impl Features {
+    /// No features.
+    pub fn none() -> Features {
+        Features(0)
+    }
+
+    /// Every combination of the given number of features, starting with none.
+    pub fn combinations(count: usize) -> Vec<Features> {
+        (0..1 << count).map(Features).collect()
+    }
+
+    /// The single feature with the given index.
+    pub fn bit(i: usize) -> Features {
+        Features(1 << i)
+    }
+
+    /// Whether all of the given features are selected.
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The names of the selected features, given the names of all of them.
+    pub fn defines(self, names: &[&'static str]) -> Vec<&'static str> {
+        names.iter()
+            .enumerate()
+            .filter(|&(i, _)| self.contains(Features::bit(i)))
+            .map(|(_, &n)| n)
+            .collect()
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+}
+
 /// The painter is responsible for drawing meshes. Painters
 /// are instantiated with an associated style which specifies
 /// the data required for drawing (vertex type, material params,
 /// configuration) and implements the drawing pipeline. Note that
 /// a painter can only be used with primitive types that have been
-/// passed to `setup`.
+/// passed to `setup` (or `setup_with`).
+///
+/// The painter keeps a variant of the style for each combination of primitive, features
+/// (see `Features`) and view mode. `setup` only compiles the variant without features.
+/// Since drawing has no factory, the variants for other features and for single-pass
+/// stereo are compiled by `prepare` once a mesh has been drawn with them. Until then,
+/// meshes are drawn with the variant without features, and stereo targets one view at
+/// a time.
 pub struct Painter<R: Resources, E: Style<R>> {
+    id: usize,
     inputs: RefCell<E::Inputs>,
-    map: FnvHashMap<Primitive, E>,
+    options: RasterOptions,
+    prims: FnvHashMap<Primitive, RasterOptions>,
+    variants: FnvHashMap<(Primitive, Features, ViewMode), E>,
+    pending: RefCell<Vec<(Primitive, Features, ViewMode)>>,
+    failed: FnvHashSet<(Primitive, Features, ViewMode)>,
 }
 
 impl<R: Resources, E: Style<R>> Painter<R, E> {
     /// Create a new painter in the given style and using the given factory.
     pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<Painter<R, E>, Error> {
-        Ok(Painter {
-            inputs: RefCell::new(E::init(f)?),
-            map: Default::default(),
-        })
+        Painter::with_options(f, Default::default())
+    }
+
+    /// Create a new painter whose primitives are set up with the given rasterizer and
+    /// depth options by default.
+    pub fn with_options<F: Factory<R> + FactoryExt<R>>(f: &mut F, options: RasterOptions)
+        -> Result<Painter<R, E>, Error>
+    {
+        Ok(Painter::with_inputs(E::init(f)?, options))
+    }
+
+    /// Create a new painter from style inputs that have already been created, e.g. by
+    /// `CustomInputs::new`.
+    pub fn with_inputs(inputs: E::Inputs, options: RasterOptions) -> Painter<R, E> {
+        Painter {
+            id: PipelineKey::new_owner(),
+            inputs: RefCell::new(inputs),
+            options: options,
+            prims: Default::default(),
+            variants: Default::default(),
+            pending: RefCell::new(Vec::new()),
+            failed: Default::default(),
+        }
     }
 
     /// Add the ability to draw the given primitive. This must be done before a mesh using
     /// the primitive is drawn.
     pub fn setup<F: Factory<R> + FactoryExt<R>>(&mut self, f: &mut F, prim: Primitive) -> Result<(), Error> {
-        let mut inputs = self.inputs.borrow_mut();
-        use ::std::collections::hash_map::Entry::*;
-        match self.map.entry(prim) {
-            Vacant(e) => {
-                e.insert(E::new(f, &mut *inputs, prim, Rasterizer::new_fill())?);
+        if !self.prims.contains_key(&prim) {
+            let options = self.options;
+            self.setup_with(f, prim, options)?;
+        }
+        Ok(())
+    }
+
+    /// Add the ability to draw the given primitive with the given rasterizer and depth
+    /// options, replacing any earlier setup of the primitive (and its variants). This
+    /// compiles the variant without features, and the others are compiled on demand (see
+    /// `prepare`).
+    pub fn setup_with<F: Factory<R> + FactoryExt<R>>(&mut self, f: &mut F, prim: Primitive, options: RasterOptions)
+        -> Result<(), Error>
+    {
+        let sty = E::new(f, self.inputs.get_mut(), prim, &options, Features::none(), ViewMode::Single)?;
+        self.variants.retain(|&(p, _, _), _| p != prim);
+        self.variants.insert((prim, Features::none(), ViewMode::Single), sty);
+        self.failed.retain(|&(p, _, _)| p != prim);
+        self.prims.insert(prim, options);
+        Ok(())
+    }
+
+    /// The options that the given primitive was set up with, if it has been.
+    pub fn options(&self, prim: Primitive) -> Option<&RasterOptions> {
+        self.prims.get(&prim)
+    }
+
+    /// Compile the variant of the style for the given primitive, features and view mode,
+    /// unless it has been already.
+    fn variant<F: Factory<R> + FactoryExt<R>>(&mut self, f: &mut F, key: (Primitive, Features, ViewMode))
+        -> Result<(), Error>
+    {
+        if self.variants.contains_key(&key) { return Ok(()) }
+        let (prim, features, mode) = key;
+        let options = *self.prims.get(&prim).ok_or_else(|| FlightError::InvalidPrimitive { given: prim }
+            .context("setup has not been done for this primitive type".to_owned()))?;
+        let sty = E::new(f, &mut *self.inputs.borrow_mut(), prim, &options, features, mode)?;
+        self.variants.insert(key, sty);
+        Ok(())
+    }
+
+    /// Queue a variant to be compiled by `prepare`, unless it has failed to compile.
+    fn request(&self, key: (Primitive, Features, ViewMode)) {
+        if self.failed.contains(&key) { return }
+        let mut pending = self.pending.borrow_mut();
+        if !pending.contains(&key) { pending.push(key) }
+    }
+
+    /// Compile the variants that meshes have been drawn with since the last call, so that
+    /// they are drawn with the features of their materials, and in a single pass into
+    /// stereo targets, from then on. This is cheap to call every frame. Variants that fail
+    /// to compile are not tried again until the painter is reloaded, and their meshes keep
+    /// being drawn without features or one view at a time. All of the variants are tried,
+    /// and the first error is returned.
+    pub fn prepare<F: Factory<R> + FactoryExt<R>>(&mut self, f: &mut F) -> Result<(), Error> {
+        let pending = mem::replace(self.pending.get_mut(), Vec::new());
+        let mut result = Ok(());
+        for key in pending {
+            if let Err(e) = self.variant(f, key) {
+                error!("Could not compile the variant for {:?} with {:?} ({:?}): {}", key.0, key.1, key.2, e);
+                self.failed.insert(key);
+                if result.is_ok() { result = Err(e) }
+            }
+        }
+        result
+    }
+
+    /// The key of the pipeline of a variant of the style.
+    fn pipeline_key(&self, prim: Primitive, features: Features, mode: ViewMode) -> PipelineKey {
+        PipelineKey {
+            owner: self.id,
+            prim: prim,
+            features: features,
+            mode: mode,
+        }
+    }
+
+    /// The number of compiled variants of the style, across primitives and features.
+    pub fn variant_count(&self) -> usize {
+        self.variants.len()
+    }
+
+    /// Rebuild the shaders and pipeline states of the painter from the current shader
+    /// sources, e.g. after files in the runtime shader directory (see `set_shader_dir`
+    /// and `watch::ShaderWatcher`) have changed. The shaders and pipeline states are only
+    /// replaced if all of them build.
+    pub fn try_reload<F: Factory<R> + FactoryExt<R>>(&mut self, f: &mut F) -> Result<(), Error> {
+        let inputs = self.inputs.get_mut();
+        let shaders = inputs.build_shaders(f)?;
+        // the variants are built from the new shaders, which are swapped back out if any fails
+        let old = inputs.swap_shaders(shaders);
+        let prims = &self.prims;
+        let variants = self.variants.keys()
+            .map(|&(prim, features, mode)| E::new(f, inputs, prim, &prims[&prim], features, mode)
+                .map(|sty| ((prim, features, mode), sty)))
+            .collect::<Result<FnvHashMap<_, _>, _>>();
+        match variants {
+            Ok(variants) => {
+                self.variants = variants;
+                self.failed.clear();
+                Ok(())
+            },
+            Err(e) => {
+                inputs.swap_shaders(old);
+                Err(e)
             },
-            _ => (),
         }
+    }
+
+    /// Rebuild the shaders and pipeline states of the painter, logging any errors. The
+    /// painter keeps drawing with its old pipeline states if the new ones fail to build.
+    pub fn reload<F: Factory<R> + FactoryExt<R>>(&mut self, f: &mut F) {
+        if let Err(e) = self.try_reload(f) {
+            error!("Could not reload shaders: {}", e);
+        }
+    }
+
+    /// The features of the variant of the style for drawing the given mesh. If the variant
+    /// for the features of its material has not been compiled, it is queued for `prepare`
+    /// and the variant without features is used.
+    fn features(&self, mesh: &Mesh<R, E::Vertex, E::Material>) -> Result<Features, Error> {
+        if !self.prims.contains_key(&mesh.prim) {
+            return Err(FlightError::InvalidPrimitive { given: mesh.prim }
+                .context("setup has not been done for this primitive type".to_owned())
+                .into())
+        }
+        let features = E::material_features(&mesh.mat);
+        let key = (mesh.prim, features, ViewMode::Single);
+        if self.variants.contains_key(&key) {
+            Ok(features)
+        } else {
+            self.request(key);
+            Ok(Features::none())
+        }
+    }
+
+    /// Draw a mesh with the variant of the given features into one of the targets of the
+    /// draw parameters. If the stereo variant has not been compiled, it is queued for
+    /// `prepare` and the views of the stereo target are drawn separately.
+    fn draw_target<C>(
+        &self,
+        features: Features,
+        ctx: &mut DrawParams<R, C>,
+        target: DrawTarget,
+        model: Transform3<f32>,
+        mesh: &Mesh<R, E::Vertex, E::Material>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        let mode = match target {
+            DrawTarget::Stereo => ViewMode::Stereo,
+            DrawTarget::View(_) => ViewMode::Single,
+        };
+        let sty = match self.variants.get(&(mesh.prim, features, mode)) {
+            Some(sty) => sty,
+            None if mode == ViewMode::Stereo => {
+                self.request((mesh.prim, features, mode));
+                self.draw_target(features, ctx, DrawTarget::View(0), model, mesh)?;
+                return self.draw_target(features, ctx, DrawTarget::View(1), model, mesh)
+            },
+            None => bail!("The style has no variant for {:?} with {:?}", mesh.prim, features),
+        };
+        let mut inputs = self.inputs.borrow_mut();
+        let eye = match target {
+            DrawTarget::Stereo => {
+                let stereo = ctx.stereo.as_ref().ok_or_else(|| format_err!("There is no stereo target to draw into"))?;
+                inputs.transform(TransformBlock::stereo(model, &ctx.views[0].eye, &ctx.views[1].eye));
+                stereo.clone()
+            },
+            DrawTarget::View(i) => {
+                let view = &ctx.views[i];
+                inputs.transform(TransformBlock::single(model, &view.eye));
+                view.target.clone()
+            },
+        };
+        let updates = sty.draw_raw(
+            &mut *inputs,
+            &mut ctx.encoder,
+            &eye,
+            &mesh.slice,
+            mesh.buf.clone(),
+            &mesh.mat,
+        )?;
+        ctx.stats.draw(self.pipeline_key(mesh.prim, features, mode), mesh.prim, &mesh.slice, updates);
         Ok(())
     }
 
@@ -72,51 +489,35 @@ impl<R: Resources, E: Style<R>> Painter<R, E> {
         -> Result<(), Error>
         where C: CommandBuffer<R>
     {
-        if let Some(ref sty) = self.map.get(&mesh.prim) {
-            let mut inputs = self.inputs.borrow_mut();
-            let mut trans = TransformBlock {
-                eye: ctx.left.eye.to_homogeneous().downgrade(),
-                model: model.downgrade(),
-                view: ctx.left.view.downgrade(),
-                proj: ctx.left.proj.downgrade(),
-                clip_offset: ctx.left.clip_offset,
-            };
-            inputs.transform(trans.clone());
-            sty.draw_raw(
-                &mut *inputs,
-                &mut ctx.encoder,
-                ctx.color.clone(),
-                ctx.depth.clone(),
-                ctx.left.clip,
-                &mesh.slice,
-                mesh.buf.clone(),
-                &mesh.mat,
-            )?;
-
-            trans.eye = ctx.right.eye.to_homogeneous().downgrade();
-            trans.view = ctx.right.view.downgrade();
-            trans.proj = ctx.right.proj.downgrade();
-            trans.clip_offset = ctx.right.clip_offset;
-            inputs.transform(trans);
-            sty.draw_raw(
-                &mut *inputs,
-                &mut ctx.encoder,
-                ctx.color.clone(),
-                ctx.depth.clone(),
-                ctx.right.clip,
-                &mesh.slice,
-                mesh.buf.clone(),
-                &mesh.mat,
-            )?;
-
-            Ok(())
-        } else {
-            Err(
-                FlightError::InvalidPrimitive { given: mesh.prim }
-                .context("setup has not been done for this primitive type".to_owned())
-                .into()
-            )
+        let features = self.features(mesh)?;
+        for target in ctx.targets() {
+            self.draw_target(features, ctx, target, model, mesh)?;
         }
+        Ok(())
+    }
+
+    /// Record a mesh to be drawn with the given model matrix when the queue is submitted,
+    /// so that the draws of all painters are sorted to minimize state changes (see
+    /// `RenderQueue`). The mesh is sorted by the pipeline and material it is drawn with.
+    pub fn queue<'a, C>(
+        &'a self,
+        queue: &mut RenderQueue<'a, R, C>,
+        pass: Pass,
+        model: Transform3<f32>,
+        mesh: &'a Mesh<R, E::Vertex, E::Material>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        let features = self.features(mesh)?;
+        queue.record(
+            pass,
+            self.pipeline_key(mesh.prim, features, ViewMode::Single),
+            E::material_key(&mesh.mat),
+            model * Point3::origin(),
+            move |ctx, target| self.draw_target(features, ctx, target, model, mesh),
+        );
+        Ok(())
     }
 
     /// Draw a mesh with the given parameters and model matrix, logging any errors.
@@ -150,38 +551,148 @@ pub trait Style<R: Resources>: Sized {
     /// The material type required on meshes
     type Material;
 
+    /// The names of the optional shader features of the style (see `Features`)
+    fn features() -> &'static [&'static str] { &[] }
+
+    /// The features needed to draw meshes with the given material
+    fn material_features(_: &Self::Material) -> Features { Features::none() }
+
+    /// A key identifying the contents of the given material (see `MaterialKey`), by
+    /// which `RenderQueue` sorts draws
+    fn material_key(_: &Self::Material) -> u64 { 0 }
+
+    /// Build the variant of the style for the given primitive, features and view mode
     fn new<F: Factory<R> + FactoryExt<R>>(
         &mut F,
         &mut Self::Inputs,
         Primitive,
-        Rasterizer,
+        &RasterOptions,
+        Features,
+        ViewMode,
     ) -> Result<Self, Error>;
 
     fn init<F: Factory<R> + FactoryExt<R>>(
         &mut F,
     ) -> Result<Self::Inputs, Error>;
 
+    /// Draw a slice into the given targets, returning the number of constant buffers
+    /// that were updated (see `FrameStats`)
     fn draw_raw<C>(
         &self,
         &mut Self::Inputs,
         &mut Encoder<R, C>,
-        TargetRef<R>,
-        DepthRef<R>,
-        Rect,
+        &EyeTarget<R>,
         &Slice<R>,
         Buffer<R, Self::Vertex>,
         &Self::Material,
     )
-        -> Result<(), Error>
+        -> Result<usize, Error>
         where C: CommandBuffer<R>;
 }
 
+/// Single-pixel textures that stand in for material maps that were not provided.
+/// The inputs of each painter create their own, which are shared between all the
+/// meshes and variants it draws.
+#[derive(Clone)]
+pub struct DefaultTextures<R: Resources> {
+    /// Solid white in linear space
+    pub white: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    /// Solid white in sRGB space
+    pub white_srgb: Texture<R, (R8_G8_B8_A8, Srgb)>,
+    /// Solid white single-channel
+    pub white_r8: Texture<R, (R8, Unorm)>,
+    /// Flat (unperturbed) normal map
+    pub normal: Texture<R, (R8_G8_B8_A8, Unorm)>,
+}
+
+impl<R: Resources> DefaultTextures<R> {
+    /// Upload the default textures using the given factory.
+    pub fn new<F: Factory<R>>(f: &mut F) -> Result<DefaultTextures<R>, Error> {
+        Ok(DefaultTextures {
+            white: Texture::uniform_value(f, [0xFF; 4])?,
+            white_srgb: Texture::uniform_value(f, [0xFF; 4])?,
+            white_r8: Texture::uniform_value(f, 0xFF)?,
+            normal: Texture::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
+        })
+    }
+}
+
+/// Builds the key of a material (see `Style::material_key`) from the handles of its
+/// textures and the bits of its factors, so that equal materials have equal keys.
+#[derive(Default)]
+pub struct MaterialKey(FnvHasher);
+
+impl MaterialKey {
+    /// Start a key with no textures or factors.
+    pub fn new() -> MaterialKey {
+        MaterialKey(FnvHasher::default())
+    }
+
+    /// Add a material map, which may be missing.
+    pub fn texture<R: Resources, T: TextureFormat>(mut self, map: &Option<Texture<R, T>>) -> MaterialKey {
+        map.as_ref().map(|t| (&t.buffer, &t.sampler)).hash(&mut self.0);
+        self
+    }
+
+    /// Add some factors.
+    pub fn factors(mut self, factors: &[f32]) -> MaterialKey {
+        for f in factors {
+            f.to_bits().hash(&mut self.0);
+        }
+        self
+    }
+
+    /// The key of the textures and factors added so far.
+    pub fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+}
+
+/// A constant buffer holding a single block, which is only updated when the block
+/// changes, e.g. the transform or the material of consecutive draws.
+pub struct BlockBuffer<R: Resources, T> {
+    buffer: Buffer<R, T>,
+    last: Option<T>,
+}
+
+impl<R: Resources, T: Copy + PartialEq> BlockBuffer<R, T> {
+    /// Create the buffer, which is updated by the first call to `update`.
+    pub fn new<F: Factory<R>>(f: &mut F) -> BlockBuffer<R, T> {
+        BlockBuffer {
+            buffer: f.create_constant_buffer(1),
+            last: None,
+        }
+    }
+
+    /// Update the buffer to hold the given block, unless it already does, returning
+    /// the number of updates made.
+    pub fn update<C: CommandBuffer<R>>(&mut self, enc: &mut Encoder<R, C>, block: &T) -> usize {
+        if self.last.as_ref() == Some(block) { return 0 }
+        enc.update_constant_buffer(&self.buffer, block);
+        self.last = Some(*block);
+        1
+    }
+
+    /// The buffer, for pipeline data
+    pub fn buffer(&self) -> &Buffer<R, T> {
+        &self.buffer
+    }
+}
+
 /// Required configuration options for a `Style`
 pub trait StyleInputs<R: Resources> {
+    /// The shaders, and any other state built from shader sources, that are rebuilt
+    /// by `Painter::reload`
+    type Shaders;
     /// Transformation matrices and eye parameters
     fn transform(&mut self, block: TransformBlock);
     /// The set of shaders used by the styler
     fn shader_set(&self) -> &ShaderSet<R>;
+    /// Build the shaders from their current sources (see `Painter::reload`), without
+    /// using them yet
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Self::Shaders, Error>;
+    /// Use the given shaders, returning the ones they replace
+    fn swap_shaders(&mut self, shaders: Self::Shaders) -> Self::Shaders;
 }
 
 mod defines {
@@ -193,7 +704,9 @@ mod defines {
             view: [[f32; 4]; 4] = "view",
             proj: [[f32; 4]; 4] = "proj",
             eye: [f32; 4] = "eye_pos",
-            clip_offset: f32 = "clip_offset",
+            right_view: [[f32; 4]; 4] = "right_view",
+            right_proj: [[f32; 4]; 4] = "right_proj",
+            right_eye: [f32; 4] = "right_eye_pos",
         }
         constant LightBlock {
             pos: [f32; 4] = "pos",
@@ -211,3 +724,34 @@ mod defines {
     }
 }
 use self::defines::*;
+
+impl TransformBlock {
+    /// Transforms for drawing a single eye. The right eye fields repeat the eye.
+    pub fn single(model: Transform3<f32>, eye: &EyeParams) -> TransformBlock {
+        TransformBlock::stereo(model, eye, eye)
+    }
+
+    /// Transforms for drawing both eyes in a single pass.
+    pub fn stereo(model: Transform3<f32>, left: &EyeParams, right: &EyeParams) -> TransformBlock {
+        TransformBlock {
+            model: model.downgrade(),
+            view: left.view.downgrade(),
+            proj: left.proj.downgrade(),
+            eye: left.eye.to_homogeneous().downgrade(),
+            right_view: right.view.downgrade(),
+            right_proj: right.proj.downgrade(),
+            right_eye: right.eye.to_homogeneous().downgrade(),
+        }
+    }
+}
+
+#[test]
+fn feature_defines() {
+    let names = &["A", "B", "C"];
+    assert_eq!(Features::none().defines(names), Vec::<&str>::new());
+    assert_eq!((Features::bit(0) | Features::bit(2)).defines(names), vec!["A", "C"]);
+    assert!(Features(0b011).contains(Features::bit(1)));
+    assert!(!Features(0b011).contains(Features(0b110)));
+    assert_eq!(Features::combinations(0), vec![Features::none()]);
+    assert_eq!(Features::combinations(2), vec![Features(0), Features(1), Features(2), Features(3)]);
+}
@@ -0,0 +1,129 @@
+use gfx::{Resources, CommandBuffer, Factory, Encoder, Rect};
+use gfx::traits::FactoryExt;
+use gfx::texture::{SamplerInfo, FilterMethod, WrapMode, Kind, AaMode};
+use gfx::memory::{Bind, Usage};
+use gfx::handle::RenderTargetView;
+use gfx::format::{R8_G8_B8_A8, Srgb, ChannelType, Swizzle};
+use std::mem;
+
+use super::{DrawParams, EyeParams, View, PostChain, HdrTarget};
+use ::{Error, Texture};
+
+/// The format of the finished image of a `RenderTexture`, matching
+/// the color maps of `UberMaterial` and `PbrMaterial`
+pub type RenderTextureFormat = (R8_G8_B8_A8, Srgb);
+
+/// An offscreen camera target, for mirrors, monitors, portals and the like. The scene is
+/// drawn from an arbitrary `EyeParams` into HDR color and depth buffers, which are then
+/// post-processed into a texture that can be used as a material map on other meshes.
+pub struct RenderTexture<R: Resources> {
+    /// The post-processing applied to the finished image
+    pub post: PostChain<R>,
+    hdr: HdrTarget<R>,
+    target: RenderTargetView<R, RenderTextureFormat>,
+    texture: Texture<R, RenderTextureFormat>,
+}
+
+impl<R: Resources> RenderTexture<R> {
+    /// Allocate a new render texture with the given size.
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F, width: u16, height: u16)
+        -> Result<RenderTexture<R>, Error>
+    {
+        RenderTexture::new_multisampled(f, width, height, 1)
+    }
+
+    /// Allocate a new render texture with the given size and number of samples per pixel.
+    pub fn new_multisampled<F: Factory<R> + FactoryExt<R>>(f: &mut F, width: u16, height: u16, samples: u8)
+        -> Result<RenderTexture<R>, Error>
+    {
+        let tex = f.create_texture::<R8_G8_B8_A8>(
+            Kind::D2(width, height, AaMode::Single),
+            1,
+            Bind::RENDER_TARGET | Bind::SHADER_RESOURCE,
+            Usage::Data,
+            Some(ChannelType::Srgb))?;
+        // The post chain writes linear values, which are encoded and decoded by the hardware
+        let target = f.view_texture_as_render_target::<RenderTextureFormat>(&tex, 0, None)?;
+        let buffer = f.view_texture_as_shader_resource::<RenderTextureFormat>(&tex, (0, 0), Swizzle::new())?;
+        let sampler = f.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        Ok(RenderTexture {
+            post: PostChain::with_output::<_, RenderTextureFormat>(f, width, height)?,
+            hdr: HdrTarget::new_multisampled(f, width, height, 1, samples)?,
+            target: target,
+            texture: Texture {
+                buffer: buffer,
+                sampler: sampler,
+            },
+        })
+    }
+
+    /// The finished image, e.g. for use as the `albedo` or `emissive` map of an `UberMaterial`.
+    pub fn texture(&self) -> Texture<R, RenderTextureFormat> {
+        self.texture.clone()
+    }
+
+    /// The width and height of the texture
+    pub fn size(&self) -> (u16, u16) {
+        self.hdr.size
+    }
+
+    /// The aspect ratio (width / height) of the texture, for building eye parameters
+    /// (see `EyeParams::from_camera`).
+    pub fn aspect(&self) -> f32 {
+        self.hdr.size.0 as f32 / self.hdr.size.1 as f32
+    }
+
+    /// The given eye parameters with the viewport covering the whole texture.
+    fn fit(&self, eye: EyeParams) -> EyeParams {
+        EyeParams {
+            clip: Rect { x: 0, y: 0, w: self.hdr.size.0, h: self.hdr.size.1 },
+            .. eye
+        }
+    }
+
+    /// A view of the scene from the given eye into this texture.
+    pub fn view(&self, eye: EyeParams) -> View<R> {
+        View {
+            eye: self.fit(eye),
+            target: self.hdr.eyes[0].clone(),
+        }
+    }
+
+    /// Draw parameters for drawing into this texture from the given eye. Call `resolve`
+    /// after drawing to update the finished image.
+    pub fn draw_params<C: CommandBuffer<R>>(&self, encoder: Encoder<R, C>, eye: EyeParams)
+        -> DrawParams<R, C>
+    {
+        DrawParams {
+            encoder: encoder,
+            views: vec![self.view(eye)],
+            stereo: None,
+            stats: Default::default(),
+        }
+    }
+
+    /// Post-process the drawn scene into the finished image.
+    pub fn resolve<C: CommandBuffer<R>>(&mut self, enc: &mut Encoder<R, C>, eye: EyeParams)
+        -> Result<(), Error>
+    {
+        let eye = self.fit(eye);
+        self.post.apply(enc, &self.hdr, &self.target, &[&eye])
+    }
+
+    /// Draw the scene from the given eye into this texture, reusing the encoder of
+    /// the given draw parameters. The targets are cleared, `draw` is called with draw
+    /// parameters that only contain this texture's view, and the result is resolved.
+    /// The views of `ctx` are restored afterwards.
+    pub fn render<C, F>(&mut self, ctx: &mut DrawParams<R, C>, eye: EyeParams, draw: F)
+        -> Result<(), Error>
+        where C: CommandBuffer<R>, F: FnOnce(&mut DrawParams<R, C>)
+    {
+        let views = mem::replace(&mut ctx.views, vec![self.view(eye)]);
+        let stereo = ctx.stereo.take();
+        ctx.clear([0.; 4], 1.);
+        draw(ctx);
+        ctx.views = views;
+        ctx.stereo = stereo;
+        self.resolve(&mut ctx.encoder, eye)
+    }
+}
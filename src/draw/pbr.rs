@@ -1,28 +1,57 @@
-use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Slice, Encoder};
 use gfx::pso::PipelineState;
 use gfx::traits::FactoryExt;
 use gfx::handle::Buffer;
-use gfx::state::Rasterizer;
 use gfx::format::*;
+use std::mem;
 
-use super::{StyleInputs, Style, LightBlock, TransformBlock};
+use super::{StyleInputs, Style, RasterOptions, Features, ViewMode, LightBlock, TransformBlock, DefaultTextures, or_default, RawDepthTarget, EyeTarget, color_target, depth_target, BlockBuffer, MaterialKey};
+use super::shaders::Shaders;
 use ::mesh::{Primitive, VertNTT};
-use ::{Light, Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+use ::{Light, Error, Texture};
 
 /// The maximum number of point lights that can be simulated
 pub const LIGHT_COUNT: usize = 4;
 
-/// The collection of mesh textures used by physically based rendering
+/// The collection of mesh textures and factors used by physically based rendering.
+/// Each map is optional and multiplied by its corresponding factor. Missing maps
+/// are replaced by white (or a flat normal map).
 #[derive(Clone)]
 pub struct PbrMaterial<R: Resources> {
     /// Normal map
-    pub normal: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    pub normal: Option<Texture<R, (R8_G8_B8_A8, Unorm)>>,
     /// Albedo map (base color)
-    pub albedo: Texture<R, (R8_G8_B8_A8, Srgb)>,
+    pub albedo: Option<Texture<R, (R8_G8_B8_A8, Srgb)>>,
     /// Metalness map (sets material to metal or dielectric)
-    pub metalness: Texture<R, (R8, Unorm)>,
+    pub metalness: Option<Texture<R, (R8, Unorm)>>,
     /// Roughness map (Changes shininess/reflection sharpness)
-    pub roughness: Texture<R, (R8, Unorm)>,
+    pub roughness: Option<Texture<R, (R8, Unorm)>>,
+    /// Linear color (and alpha) multiplied with the albedo map
+    pub albedo_factor: [f32; 4],
+    /// Multiplied with the metalness map
+    pub metalness_factor: f32,
+    /// Multiplied with the roughness map
+    pub roughness_factor: f32,
+    /// Linear color of the light given off by the surface
+    pub emissive_factor: [f32; 3],
+    /// Strength of the normal map (0=flat, 1=unchanged)
+    pub normal_factor: f32,
+}
+
+impl<R: Resources> Default for PbrMaterial<R> {
+    fn default() -> PbrMaterial<R> {
+        PbrMaterial {
+            normal: None,
+            albedo: None,
+            metalness: None,
+            roughness: None,
+            albedo_factor: [1.; 4],
+            metalness_factor: 1.,
+            roughness_factor: 1.,
+            emissive_factor: [0.; 3],
+            normal_factor: 1.,
+        }
+    }
 }
 
 gfx_defines!{
@@ -31,9 +60,9 @@ gfx_defines!{
         transform: gfx::ConstantBuffer<TransformBlock> = "transform",
         params: gfx::ConstantBuffer<PbrBlock> = "params",
         lights: gfx::ConstantBuffer<LightBlock> = "lights_layout",
-        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
-        color: gfx::RenderTarget<ColorFormat> = "f_lum",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::LESS_EQUAL_WRITE,
+        material: gfx::ConstantBuffer<MaterialBlock> = "material",
+        color: gfx::RawRenderTarget = color_target("f_lum", None),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::LESS_EQUAL_WRITE),
         normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
         albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
         metalness: gfx::TextureSampler<f32> = "metalness_tex",
@@ -43,9 +72,17 @@ gfx_defines!{
     constant PbrBlock {
         ambient: [f32; 4] = "ambient",
     }
+
+    constant MaterialBlock {
+        albedo: [f32; 4] = "albedo_factor",
+        emissive: [f32; 4] = "emissive_factor",
+        metalness: f32 = "metalness_factor",
+        roughness: f32 = "roughness_factor",
+        normal: f32 = "normal_factor",
+    }
 }
 
-shader!(shader {
+shader!(shader, stereo_shader {
     vertex: static_file!("shaders/transform.v.glsl")
         .define("NORM")
         .define("TEX")
@@ -61,13 +98,15 @@ shader!(shader {
 
 /// The configuration for physically based rendering
 pub struct PbrInputs<R: Resources> {
-    shaders: ShaderSet<R>,
+    shaders: Shaders<R>,
     transform: Option<TransformBlock>,
-    transform_block: Buffer<R, TransformBlock>,
+    transform_block: BlockBuffer<R, TransformBlock>,
     params: Option<PbrBlock>,
     params_block: Buffer<R, PbrBlock>,
     lights: Option<[LightBlock; LIGHT_COUNT]>,
     lights_block: Buffer<R, LightBlock>,
+    material_block: BlockBuffer<R, MaterialBlock>,
+    defaults: DefaultTextures<R>,
 }
 
 impl<R: Resources> PbrInputs<R> {
@@ -89,10 +128,17 @@ impl<R: Resources> PbrInputs<R> {
 }
 
 impl<R: Resources> StyleInputs<R> for PbrInputs<R> {
+    type Shaders = Shaders<R>;
     fn transform(&mut self, block: TransformBlock) {
         self.transform = Some(block);
     }
-    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+    fn shader_set(&self) -> &ShaderSet<R> { self.shaders.set() }
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Shaders<R>, Error> {
+        shader(f)
+    }
+    fn swap_shaders(&mut self, shaders: Shaders<R>) -> Shaders<R> {
+        mem::replace(&mut self.shaders, shaders)
+    }
 }
 
 /// Draws meshes using a physically based rendering pipeline
@@ -105,14 +151,36 @@ impl<R: Resources> Style<R> for PbrStyle<R> {
     type Inputs = PbrInputs<R>;
     type Material = PbrMaterial<R>;
 
+    fn material_key(mat: &PbrMaterial<R>) -> u64 {
+        MaterialKey::new()
+            .texture(&mat.normal)
+            .texture(&mat.albedo)
+            .texture(&mat.metalness)
+            .texture(&mat.roughness)
+            .factors(&mat.albedo_factor)
+            .factors(&mat.emissive_factor)
+            .factors(&[mat.metalness_factor, mat.roughness_factor, mat.normal_factor])
+            .finish()
+    }
+
     fn new<F: Factory<R> + FactoryExt<R>> (
         f: &mut F,
         i: &mut PbrInputs<R>,
         p: Primitive,
-        r: Rasterizer,
+        o: &RasterOptions,
+        _: Features,
+        mode: ViewMode,
     ) -> Result<Self, Error> {
+        let stereo;
+        let shaders = match mode {
+            ViewMode::Single => &i.shaders,
+            ViewMode::Stereo => {
+                stereo = stereo_shader(f, p)?;
+                &stereo
+            },
+        };
         Ok(PbrStyle {
-            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+            pso: shaders.pipeline_state(f, p, o.rasterizer(), pipeline_init!(pl, o))?,
         })
     }
 
@@ -122,11 +190,13 @@ impl<R: Resources> Style<R> for PbrStyle<R> {
         Ok(PbrInputs {
             shaders: shader(f)?,
             transform: None,
-            transform_block: f.create_constant_buffer(1),
+            transform_block: BlockBuffer::new(f),
             params: Some(PbrBlock { ambient: [0.; 4] }),
             params_block: f.create_constant_buffer(1),
             lights: Some([LightBlock::from(Light::default()); 4]),
             lights_block: f.create_constant_buffer(LIGHT_COUNT),
+            material_block: BlockBuffer::new(f),
+            defaults: DefaultTextures::new(f)?,
         })
     }
 
@@ -134,38 +204,47 @@ impl<R: Resources> Style<R> for PbrStyle<R> {
         &self,
         inputs: &mut PbrInputs<R>,
         enc: &mut Encoder<R, C>,
-        color: TargetRef<R>,
-        depth: DepthRef<R>,
-        scissor: Rect,
+        target: &EyeTarget<R>,
         slice: &Slice<R>,
         buf: Buffer<R, Self::Vertex>,
         mat: &PbrMaterial<R>,
     )
-        -> Result<(), Error>
+        -> Result<usize, Error>
         where C: CommandBuffer<R>
     {
+        let mut updates = 0;
         if let Some(t) = inputs.transform.take() {
-            enc.update_constant_buffer(&inputs.transform_block, &t);
+            updates += inputs.transform_block.update(enc, &t);
         }
         if let Some(l) = inputs.lights.take() {
             enc.update_buffer(&inputs.lights_block, &l, 0)?;
+            updates += 1;
         }
         if let Some(p) = inputs.params.take() {
             enc.update_constant_buffer(&inputs.params_block, &p);
+            updates += 1;
         }
+        let e = mat.emissive_factor;
+        updates += inputs.material_block.update(enc, &MaterialBlock {
+            albedo: mat.albedo_factor,
+            emissive: [e[0], e[1], e[2], 1.],
+            metalness: mat.metalness_factor,
+            roughness: mat.roughness_factor,
+            normal: mat.normal_factor,
+        });
         enc.draw(slice, &self.pso, &pl::Data {
-            color: color,
-            depth: depth,
+            color: target.color.clone(),
+            depth: target.depth.clone(),
             verts: buf,
-            scissor: scissor,
-            transform: inputs.transform_block.clone(),
+            transform: inputs.transform_block.buffer().clone(),
             params: inputs.params_block.clone(),
             lights: inputs.lights_block.clone(),
-            normal: mat.normal.clone().into_tuple(),
-            albedo: mat.albedo.clone().into_tuple(),
-            metalness: mat.metalness.clone().into_tuple(),
-            roughness: mat.roughness.clone().into_tuple(),
+            material: inputs.material_block.buffer().clone(),
+            normal: or_default(&mat.normal, &inputs.defaults.normal),
+            albedo: or_default(&mat.albedo, &inputs.defaults.white_srgb),
+            metalness: or_default(&mat.metalness, &inputs.defaults.white_r8),
+            roughness: or_default(&mat.roughness, &inputs.defaults.white_r8),
         });
-        Ok(())
+        Ok(updates)
     }
 }
@@ -0,0 +1,815 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Encoder, Rect};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{self, Buffer, ShaderResourceView, RenderTargetView};
+use gfx::state::{Rasterizer, ColorMask};
+use gfx::texture::{SamplerInfo, FilterMethod, WrapMode, Kind, AaMode, DepthStencilFlags};
+use gfx::memory::{Bind, Usage};
+use gfx::format::{self, R32, R16_G16_B16_A16, Float, ChannelType, ChannelTyped, Swizzle, Format, Formatted};
+use std::collections::VecDeque;
+use std::f32;
+
+use super::{EyeParams, EyeTarget};
+use ::mesh::{Primitive, MeshSource, Mesh, Indexing, Vert};
+use ::{Error, ColorFormat, HdrColorFormat, DepthFormat, HdrTargetRef, Texture};
+
+/// The operator used to compress HDR luminance into the displayable range
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    /// `1 - exp(-lum)`
+    Exponential,
+    /// `lum / (1 + lum)`
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2
+    Uncharted2,
+}
+
+impl ToneMap {
+    /// The index of the operator in the `tone_map` uniform of the shaders
+    pub fn index(&self) -> i32 {
+        use self::ToneMap::*;
+        match *self {
+            Exponential => 0,
+            Reinhard => 1,
+            Aces => 2,
+            Uncharted2 => 3,
+        }
+    }
+}
+
+/// How the luminance of a frame is measured for automatic exposure
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Metering {
+    /// Geometric mean of the luminance of every pixel
+    Average,
+    /// Geometric mean of the pixels between the `low` and `high` fractions of a
+    /// luminance histogram (e.g. 0.5 and 0.95 ignore the darkest half and the
+    /// brightest 5% of the frame)
+    Histogram {
+        low: f32,
+        high: f32,
+    },
+}
+
+/// Configuration for automatic exposure (eye adaptation)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AutoExposure {
+    /// How frame luminance is measured
+    pub metering: Metering,
+    /// The exposed luminance that the measured luminance is mapped to (middle gray)
+    pub key: f32,
+    /// Adaptation rate (1/s) when the image needs to get brighter (exposure rises)
+    pub brighten_speed: f32,
+    /// Adaptation rate (1/s) when the image needs to get darker (exposure falls)
+    pub darken_speed: f32,
+    /// Lowest allowed exposure
+    pub min_exposure: f32,
+    /// Highest allowed exposure
+    pub max_exposure: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> AutoExposure {
+        AutoExposure {
+            metering: Metering::Histogram { low: 0.5, high: 0.95 },
+            key: 0.18,
+            brighten_speed: 1.,
+            darken_speed: 3.,
+            min_exposure: 0.01,
+            max_exposure: 100.,
+        }
+    }
+}
+
+/// The size of the luminance buffer used for metering
+const METER_SIZE: u16 = 64;
+/// The number of frames between metering and reading back the luminance, so that the
+/// read back does not wait for the GPU to finish the frame
+const METER_LATENCY: usize = 2;
+/// Histogram bins used by `Metering::Histogram`
+const HISTOGRAM_BINS: usize = 64;
+/// The log2 luminance range covered by the histogram
+const HISTOGRAM_RANGE: (f32, f32) = (-16., 16.);
+
+/// The pixel format of the metering buffer (log2 luminance)
+type MeterFormat = (R32, Float);
+
+/// Measure the log2 luminance of a set of log2 luminance samples.
+fn meter_log_luminance(samples: &[f32], metering: Metering) -> Option<f32> {
+    match metering {
+        Metering::Average => {
+            if samples.is_empty() { return None }
+            Some(samples.iter().sum::<f32>() / samples.len() as f32)
+        },
+        Metering::Histogram { low, high } => {
+            let (min, max) = HISTOGRAM_RANGE;
+            let bin_of = |v: f32| {
+                let t = (v - min) / (max - min);
+                ((t * HISTOGRAM_BINS as f32) as isize).max(0).min(HISTOGRAM_BINS as isize - 1) as usize
+            };
+            let mut bins = [0usize; HISTOGRAM_BINS];
+            for &v in samples {
+                bins[bin_of(v)] += 1;
+            }
+
+            // average the bin centers between the low and high percentiles
+            let total = samples.len() as f32;
+            let low = low.max(0.).min(1.) * total;
+            let high = high.max(0.).min(1.) * total;
+            let mut seen = 0.;
+            let mut sum = 0.;
+            let mut count = 0.;
+            for (i, &n) in bins.iter().enumerate() {
+                let n = n as f32;
+                let take = (seen + n).min(high) - seen.max(low);
+                if take > 0. {
+                    let center = min + (i as f32 + 0.5) / HISTOGRAM_BINS as f32 * (max - min);
+                    sum += center * take;
+                    count += take;
+                }
+                seen += n;
+            }
+            if count > 0. { Some(sum / count) } else { None }
+        },
+    }
+}
+
+/// Move the exposure toward the exposure that maps the given log2 luminance onto
+/// the key value, at the configured speed, over a time step of `dt` seconds.
+fn adapt_exposure(exposure: f32, log_lum: f32, cfg: &AutoExposure, dt: f32) -> f32 {
+    // a minimum of 0 would put the exposure at -inf in log space
+    let min = cfg.min_exposure.max(f32::MIN_POSITIVE);
+    let target = (cfg.key.log2() - log_lum).max(min.log2()).min(cfg.max_exposure.log2());
+    let current = exposure.max(min).min(cfg.max_exposure).log2();
+    let speed = if target > current { cfg.brighten_speed } else { cfg.darken_speed };
+    let frac = 1. - (-speed * dt.max(0.)).exp();
+    (current + (target - current) * frac).exp2()
+}
+
+gfx_defines!{
+    constant PostBlock {
+        rect: [f32; 4] = "rect",
+        blur_dir: [f32; 4] = "blur_dir",
+        exposure: f32 = "exposure",
+        gamma: f32 = "gamma",
+        tone_map: i32 = "tone_map",
+        bloom_strength: f32 = "bloom_strength",
+        bloom_threshold: f32 = "bloom_threshold",
+        layer: i32 = "layer",
+        srgb: i32 = "srgb",
+    }
+
+    pipeline filter {
+        verts: gfx::VertexBuffer<Vert> = (),
+        params: gfx::ConstantBuffer<PostBlock> = "params",
+        source: gfx::TextureSampler<[f32; 4]> = "source",
+        color: gfx::RenderTarget<HdrColorFormat> = "f_color",
+    }
+
+    pipeline resolve {
+        verts: gfx::VertexBuffer<Vert> = (),
+        params: gfx::ConstantBuffer<PostBlock> = "params",
+        source: gfx::ShaderResource<[f32; 4]> = "source",
+        samples: gfx::Global<i32> = "samples",
+        color: gfx::RenderTarget<HdrColorFormat> = "f_color",
+    }
+
+    pipeline meter {
+        verts: gfx::VertexBuffer<Vert> = (),
+        params: gfx::ConstantBuffer<PostBlock> = "params",
+        source: gfx::TextureSampler<[f32; 4]> = "source",
+        color: gfx::RenderTarget<MeterFormat> = "f_lum",
+    }
+
+    pipeline output {
+        verts: gfx::VertexBuffer<Vert> = (),
+        params: gfx::ConstantBuffer<PostBlock> = "params",
+        source: gfx::TextureSampler<[f32; 4]> = "source",
+        bloom: gfx::TextureSampler<[f32; 4]> = "bloom",
+        color: gfx::RawRenderTarget = ("f_color", ColorFormat::get_format(), ColorMask::all(), None),
+    }
+}
+
+shader!(bright_shader {
+    vertex: static_file!("shaders/post.v.glsl"),
+    fragment: static_file!("shaders/bright.f.glsl")
+});
+
+shader!(blur_shader {
+    vertex: static_file!("shaders/post.v.glsl"),
+    fragment: static_file!("shaders/blur.f.glsl")
+});
+
+shader!(resolve_shader {
+    vertex: static_file!("shaders/post.v.glsl"),
+    fragment: static_file!("shaders/resolve.f.glsl")
+});
+
+shader!(meter_shader {
+    vertex: static_file!("shaders/post.v.glsl"),
+    fragment: static_file!("shaders/meter.f.glsl")
+});
+
+shader!(output_shader {
+    vertex: static_file!("shaders/post.v.glsl"),
+    fragment: static_file!("shaders/tonemap.f.glsl")
+});
+
+/// Floating-point color targets (with depth) that painters draw into before post-processing,
+/// with one layer per eye. The alpha of the color targets is opacity, so transparent
+/// surfaces are blended in linear space.
+///
+/// A multisampled target draws into multisampled color and depth buffers, which the
+/// `PostChain` resolves into `texture` before post-processing.
+pub struct HdrTarget<R: Resources> {
+    /// The draw targets of each eye
+    pub eyes: Vec<EyeTarget<R>>,
+    /// The draw targets of all eyes at once, for single-pass stereo (see `DrawParams::stereo`)
+    pub layered: EyeTarget<R>,
+    /// The (resolved) color buffers as an array texture, read by post-processing
+    pub texture: Texture<R, HdrColorFormat>,
+    /// The width and height of each eye's target
+    pub size: (u16, u16),
+    /// The number of samples per pixel
+    pub samples: u8,
+    resolved: handle::Texture<R, R16_G16_B16_A16>,
+    multisample: Option<Multisample<R>>,
+}
+
+/// The single-sampled side of a multisampled `HdrTarget`
+struct Multisample<R: Resources> {
+    source: ShaderResourceView<R, [f32; 4]>,
+    resolved: Vec<HdrTargetRef<R>>,
+}
+
+/// A layered color texture with a view of each of its layers and a view of all layers
+#[derive(Clone)]
+struct HdrLayers<R: Resources> {
+    texture: handle::Texture<R, R16_G16_B16_A16>,
+    view: ShaderResourceView<R, [f32; 4]>,
+    layers: Vec<HdrTargetRef<R>>,
+    all: HdrTargetRef<R>,
+}
+
+impl<R: Resources> HdrLayers<R> {
+    fn new<F: Factory<R>>(f: &mut F, kind: Kind) -> Result<HdrLayers<R>, Error> {
+        let bind = match kind.get_aa_mode() {
+            AaMode::Single => Bind::RENDER_TARGET | Bind::SHADER_RESOURCE | Bind::TRANSFER_SRC,
+            _ => Bind::RENDER_TARGET | Bind::SHADER_RESOURCE,
+        };
+        let texture = f.create_texture::<R16_G16_B16_A16>(kind, 1, bind, Usage::Data, Some(ChannelType::Float))?;
+        let mut layers = Vec::new();
+        for i in 0..kind.get_num_slices().unwrap_or(1) {
+            layers.push(f.view_texture_as_render_target::<HdrColorFormat>(&texture, 0, Some(i))?);
+        }
+        Ok(HdrLayers {
+            view: f.view_texture_as_shader_resource::<HdrColorFormat>(&texture, (0, 0), Swizzle::new())?,
+            all: f.view_texture_as_render_target::<HdrColorFormat>(&texture, 0, None)?,
+            layers: layers,
+            texture: texture,
+        })
+    }
+}
+
+impl<R: Resources> HdrTarget<R> {
+    /// Allocate a new HDR target with the given size and number of eyes.
+    pub fn new<F: Factory<R>>(f: &mut F, width: u16, height: u16, eyes: u16) -> Result<HdrTarget<R>, Error> {
+        HdrTarget::new_multisampled(f, width, height, eyes, 1)
+    }
+
+    /// Allocate a new HDR target with the given size, number of eyes, and number of samples
+    /// per pixel. A sample count of 1 (or 0) gives a regular single-sampled target.
+    pub fn new_multisampled<F: Factory<R>>(f: &mut F, width: u16, height: u16, eyes: u16, samples: u8)
+        -> Result<HdrTarget<R>, Error>
+    {
+        HdrTarget::with_depth::<F, DepthFormat>(f, width, height, eyes, samples)
+    }
+
+    /// Allocate a new HDR target like `new_multisampled`, with depth buffers of the given
+    /// format, e.g. `(D32, Float)`. Painters drawing into the target must be set up for
+    /// the format (see `RasterOptions::formats`).
+    pub fn with_depth<F: Factory<R>, D: format::DepthFormat>(f: &mut F, width: u16, height: u16, eyes: u16, samples: u8)
+        -> Result<HdrTarget<R>, Error>
+    {
+        let samples = samples.max(1);
+        let aa = if samples > 1 { AaMode::Multi(samples) } else { AaMode::Single };
+        let kind = Kind::D2Array(width, height, eyes, aa);
+
+        let draw = HdrLayers::new(f, kind)?;
+
+        let depth_tex = f.create_texture::<D::Surface>(
+            kind,
+            1,
+            Bind::DEPTH_STENCIL,
+            Usage::Data,
+            Some(<D::Channel as ChannelTyped>::get_channel_type()))?;
+        let mut depths = Vec::new();
+        for i in 0..eyes {
+            depths.push(f.view_texture_as_depth_stencil::<D>(
+                &depth_tex, 0, Some(i), DepthStencilFlags::empty())?);
+        }
+        let layered_depth = f.view_texture_as_depth_stencil::<D>(
+            &depth_tex, 0, None, DepthStencilFlags::empty())?;
+
+        let (resolved, multisample) = if samples > 1 {
+            let resolved = HdrLayers::new(f, Kind::D2Array(width, height, eyes, AaMode::Single))?;
+            let ms = Multisample {
+                source: draw.view.clone(),
+                resolved: resolved.layers.clone(),
+            };
+            (resolved, Some(ms))
+        } else {
+            (draw.clone(), None)
+        };
+
+        let sampler = f.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        Ok(HdrTarget {
+            eyes: draw.layers.iter().zip(&depths).map(|(c, d)| EyeTarget::new(c, d)).collect(),
+            layered: EyeTarget::new(&draw.all, &layered_depth),
+            texture: Texture {
+                buffer: resolved.view,
+                sampler: sampler,
+            },
+            size: (width, height),
+            samples: samples,
+            resolved: resolved.texture,
+            multisample: multisample,
+        })
+    }
+
+    /// The (resolved) color array texture behind `texture`, e.g. for reading back.
+    pub fn color_texture(&self) -> &handle::Texture<R, R16_G16_B16_A16> {
+        &self.resolved
+    }
+}
+
+/// An intermediate color-only buffer used by the post chain
+struct PostBuffer<R: Resources> {
+    target: HdrTargetRef<R>,
+    texture: Texture<R, HdrColorFormat>,
+}
+
+impl<R: Resources> PostBuffer<R> {
+    fn new<F: Factory<R>>(f: &mut F, width: u16, height: u16) -> Result<PostBuffer<R>, Error> {
+        let (_, buffer, target) = f.create_render_target::<HdrColorFormat>(width, height)?;
+        let sampler = f.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        Ok(PostBuffer {
+            target: target,
+            texture: Texture {
+                buffer: buffer,
+                sampler: sampler,
+            },
+        })
+    }
+}
+
+/// Measures the luminance of each eye region so that exposure can adapt to it. The
+/// luminance is copied into a ring of download buffers and read back `METER_LATENCY`
+/// frames later.
+struct Meter<R: Resources> {
+    pso: PipelineState<R, meter::Meta>,
+    texture: handle::Texture<R, R32>,
+    target: handle::RenderTargetView<R, MeterFormat>,
+    /// Download buffers that no measurement is waiting in
+    free: Vec<Buffer<R, f32>>,
+    /// Measurements waiting to be read back (oldest first), with the rects of their eyes
+    pending: VecDeque<(Buffer<R, f32>, Vec<[f32; 4]>)>,
+    luminance: Vec<f32>,
+}
+
+impl<R: Resources> Meter<R> {
+    fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<Meter<R>, Error> {
+        let texture = f.create_texture(
+            Kind::D2(METER_SIZE, METER_SIZE, AaMode::Single),
+            1,
+            Bind::RENDER_TARGET | Bind::TRANSFER_SRC,
+            Usage::Data,
+            Some(ChannelType::Float))?;
+        let target = f.view_texture_as_render_target::<MeterFormat>(&texture, 0, None)?;
+        Ok(Meter {
+            pso: meter_shader(f)?.pipeline_state(
+                f,
+                Primitive::TriangleStrip,
+                Rasterizer::new_fill(),
+                meter::new())?,
+            texture: texture,
+            target: target,
+            free: (0..METER_LATENCY + 1)
+                .map(|_| f.create_download_buffer(METER_SIZE as usize * METER_SIZE as usize))
+                .collect::<Result<_, _>>()?,
+            pending: VecDeque::new(),
+            luminance: Vec::new(),
+        })
+    }
+
+    /// A download buffer to copy a new measurement into. If every buffer is waiting to be
+    /// read back, the oldest measurement is dropped.
+    fn next_download(&mut self) -> Buffer<R, f32> {
+        match self.free.pop() {
+            Some(buf) => buf,
+            None => self.pending.pop_front().expect("The meter has no download buffers").0,
+        }
+    }
+
+    /// The region of the meter buffer that measures eye `i` of `n`.
+    fn rect(i: usize, n: usize) -> [f32; 4] {
+        [i as f32 / n as f32, 0., (i + 1) as f32 / n as f32, 1.]
+    }
+
+    /// Read back the luminance measured by `PostChain::apply` `METER_LATENCY` frames ago,
+    /// if there is one, and return its log2 average over all eyes.
+    fn read<F: Factory<R>>(&mut self, f: &mut F, metering: Metering) -> Result<Option<f32>, Error> {
+        if self.pending.len() <= METER_LATENCY { return Ok(None) }
+        let (download, rects) = self.pending.pop_front().expect("No pending measurement");
+        let result = self.measure(f, &download, &rects, metering);
+        self.free.push(download);
+        result
+    }
+
+    /// Measure the log2 average luminance of the eye rects in a download buffer.
+    fn measure<F: Factory<R>>(&mut self, f: &mut F, download: &Buffer<R, f32>, rects: &[[f32; 4]], metering: Metering)
+        -> Result<Option<f32>, Error>
+    {
+        let data = f.read_mapping(download)?;
+        let size = METER_SIZE as usize;
+
+        self.luminance.clear();
+        let mut log_sum = 0.;
+        for r in rects {
+            let x0 = (r[0] * size as f32) as usize;
+            let y0 = (r[1] * size as f32) as usize;
+            let x1 = ((r[2] * size as f32) as usize).min(size);
+            let y1 = ((r[3] * size as f32) as usize).min(size);
+            let mut samples = Vec::with_capacity((x1 - x0) * (y1 - y0));
+            for y in y0..y1 {
+                samples.extend(&data[(y * size + x0)..(y * size + x1)]);
+            }
+            if let Some(l) = meter_log_luminance(&samples, metering) {
+                self.luminance.push(l.exp2());
+                log_sum += l;
+            }
+        }
+        Ok(if self.luminance.is_empty() {
+            None
+        } else {
+            Some(log_sum / self.luminance.len() as f32)
+        })
+    }
+}
+
+/// Converts an `HdrTarget` into displayable colors. For each eye region, the chain
+/// applies exposure, bloom, tone mapping, and gamma correction in a single place.
+/// Multisampled targets are resolved first.
+///
+/// The chain writes into display targets of a single format, `ColorFormat` unless it
+/// was created with `with_output`. Gamma correction is left to the hardware for sRGB
+/// formats.
+pub struct PostChain<R: Resources> {
+    /// The tone mapping operator
+    pub tone_map: ToneMap,
+    /// Linear multiplier applied to HDR luminance before tone mapping
+    pub exposure: f32,
+    /// The display gamma, which is not applied to sRGB display targets
+    pub gamma: f32,
+    /// How much bloom is added to the image (0 disables bloom)
+    pub bloom_strength: f32,
+    /// Exposed luminance above which pixels begin to bloom
+    pub bloom_threshold: f32,
+    /// The number of blur iterations used to spread bloom
+    pub bloom_passes: u8,
+    /// Automatically adjust `exposure` to the brightness of the scene (see `adapt`)
+    pub auto_exposure: Option<AutoExposure>,
+    meter: Meter<R>,
+    resolve_pso: PipelineState<R, resolve::Meta>,
+    quad: Mesh<R, Vert, ()>,
+    params_block: Buffer<R, PostBlock>,
+    bright_pso: PipelineState<R, filter::Meta>,
+    blur_pso: PipelineState<R, filter::Meta>,
+    output_pso: PipelineState<R, output::Meta>,
+    output_format: Format,
+    bloom: [PostBuffer<R>; 2],
+}
+
+/// The whole of a target in normalized coordinates
+const FULL_RECT: [f32; 4] = [0., 0., 1., 1.];
+
+/// Convert an eye's viewport into normalized [0, 1] coordinates of a target.
+fn unit_rect(eye: &EyeParams, size: (u16, u16)) -> [f32; 4] {
+    let w = size.0 as f32;
+    let h = size.1 as f32;
+    [
+        eye.clip.x as f32 / w,
+        eye.clip.y as f32 / h,
+        (eye.clip.x + eye.clip.w) as f32 / w,
+        (eye.clip.y + eye.clip.h) as f32 / h,
+    ]
+}
+
+/// Convert a normalized rectangle into normalized device coordinates.
+fn ndc_rect(r: [f32; 4]) -> [f32; 4] {
+    [r[0] * 2. - 1., r[1] * 2. - 1., r[2] * 2. - 1., r[3] * 2. - 1.]
+}
+
+impl<R: Resources> PostChain<R> {
+    /// Create a post chain for HDR targets with the given (per eye) size.
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F, width: u16, height: u16) -> Result<PostChain<R>, Error> {
+        PostChain::with_output::<F, ColorFormat>(f, width, height)
+    }
+
+    /// Create a post chain for HDR targets with the given (per eye) size, writing into
+    /// display targets of the given format, e.g. `(R8_G8_B8_A8, Srgb)`.
+    pub fn with_output<F, T>(f: &mut F, width: u16, height: u16) -> Result<PostChain<R>, Error>
+        where F: Factory<R> + FactoryExt<R>, T: format::RenderFormat
+    {
+        let bw = (width / 2).max(1);
+        let bh = (height / 2).max(1);
+        let quad = MeshSource {
+            verts: vec![
+                Vert { pos: [0., 0., 0.] },
+                Vert { pos: [1., 0., 0.] },
+                Vert { pos: [0., 1., 0.] },
+                Vert { pos: [1., 1., 0.] },
+            ],
+            inds: Indexing::All,
+            prim: Primitive::TriangleStrip,
+            mat: (),
+        }.upload(f);
+        Ok(PostChain {
+            tone_map: ToneMap::Exponential,
+            exposure: 1.,
+            gamma: 2.2,
+            bloom_strength: 0.,
+            bloom_threshold: 1.,
+            bloom_passes: 2,
+            auto_exposure: None,
+            meter: Meter::new(f)?,
+            resolve_pso: resolve_shader(f)?.pipeline_state(
+                f,
+                Primitive::TriangleStrip,
+                Rasterizer::new_fill(),
+                resolve::new())?,
+            quad: quad,
+            params_block: f.create_constant_buffer(1),
+            bright_pso: bright_shader(f)?.pipeline_state(
+                f,
+                Primitive::TriangleStrip,
+                Rasterizer::new_fill(),
+                filter::new())?,
+            blur_pso: blur_shader(f)?.pipeline_state(
+                f,
+                Primitive::TriangleStrip,
+                Rasterizer::new_fill(),
+                filter::new())?,
+            output_pso: output_shader(f)?.pipeline_state(
+                f,
+                Primitive::TriangleStrip,
+                Rasterizer::new_fill(),
+                output::Init {
+                    color: ("f_color", T::get_format(), ColorMask::all(), None),
+                    .. output::new()
+                })?,
+            output_format: T::get_format(),
+            bloom: [PostBuffer::new(f, bw, bh)?, PostBuffer::new(f, bw, bh)?],
+        })
+    }
+
+    fn params(&self, rect: [f32; 4], blur_dir: [f32; 2], layer: usize) -> PostBlock {
+        PostBlock {
+            rect: ndc_rect(rect),
+            blur_dir: [blur_dir[0], blur_dir[1], 0., 0.],
+            exposure: self.exposure,
+            gamma: self.gamma,
+            tone_map: self.tone_map.index(),
+            bloom_strength: self.bloom_strength,
+            bloom_threshold: self.bloom_threshold,
+            layer: layer as i32,
+            srgb: (self.output_format.1 == ChannelType::Srgb) as i32,
+        }
+    }
+
+    /// The format of the display targets that the chain writes.
+    pub fn output_format(&self) -> Format {
+        self.output_format
+    }
+
+    fn filter<C: CommandBuffer<R>>(
+        &self,
+        enc: &mut Encoder<R, C>,
+        pso: &PipelineState<R, filter::Meta>,
+        params: PostBlock,
+        source: &Texture<R, HdrColorFormat>,
+        target: &HdrTargetRef<R>,
+    ) {
+        enc.update_constant_buffer(&self.params_block, &params);
+        enc.draw(&self.quad.slice, pso, &filter::Data {
+            verts: self.quad.buf.clone(),
+            params: self.params_block.clone(),
+            source: source.clone().into_tuple(),
+            color: target.clone(),
+        });
+    }
+
+    /// Adapt the exposure to the luminance measured by `apply` a couple of frames ago, if
+    /// automatic exposure is enabled. This should be called once per frame, after the
+    /// previous frame's commands have been flushed. The luminance is read back with some
+    /// latency so that this does not wait for the GPU. `dt` is the time since the last call
+    /// in seconds.
+    pub fn adapt<F: Factory<R>>(&mut self, f: &mut F, dt: f32) -> Result<(), Error> {
+        let cfg = match self.auto_exposure {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        if let Some(log_lum) = self.meter.read(f, cfg.metering)? {
+            self.exposure = adapt_exposure(self.exposure, log_lum, &cfg, dt);
+        }
+        Ok(())
+    }
+
+    /// The current exposure (which changes over time if automatic exposure is enabled).
+    pub fn current_exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// The average luminance of each eye measured for automatic exposure, in the order
+    /// the eyes were passed to `apply`. This is empty until `adapt` has run with automatic exposure enabled.
+    pub fn metered_luminance(&self) -> &[f32] {
+        &self.meter.luminance
+    }
+
+    /// Resolve a layer of a multisampled source (no-op for single-sampled sources).
+    fn resolve_layer<C: CommandBuffer<R>>(&self, enc: &mut Encoder<R, C>, source: &HdrTarget<R>, layer: usize) {
+        if let Some(ref ms) = source.multisample {
+            enc.update_constant_buffer(&self.params_block, &self.params(FULL_RECT, [0., 0.], layer));
+            enc.draw(&self.quad.slice, &self.resolve_pso, &resolve::Data {
+                verts: self.quad.buf.clone(),
+                params: self.params_block.clone(),
+                source: ms.source.clone(),
+                samples: source.samples as i32,
+                color: ms.resolved[layer].clone(),
+            });
+        }
+    }
+
+    /// Bloom and tone map a (resolved) layer of the source into a rectangle of the target.
+    fn output_layer<C: CommandBuffer<R>>(
+        &self,
+        enc: &mut Encoder<R, C>,
+        source: &HdrTarget<R>,
+        target: &handle::RawRenderTargetView<R>,
+        layer: usize,
+        rect: [f32; 4],
+    ) {
+        if self.bloom_strength > 0. {
+            self.filter(enc,
+                &self.bright_pso,
+                self.params(FULL_RECT, [0., 0.], layer),
+                &source.texture,
+                &self.bloom[0].target);
+            for _ in 0..self.bloom_passes {
+                self.filter(enc,
+                    &self.blur_pso,
+                    self.params(FULL_RECT, [1., 0.], layer),
+                    &self.bloom[0].texture,
+                    &self.bloom[1].target);
+                self.filter(enc,
+                    &self.blur_pso,
+                    self.params(FULL_RECT, [0., 1.], layer),
+                    &self.bloom[1].texture,
+                    &self.bloom[0].target);
+            }
+        }
+        enc.update_constant_buffer(&self.params_block, &self.params(rect, [0., 0.], layer));
+        enc.draw(&self.quad.slice, &self.output_pso, &output::Data {
+            verts: self.quad.buf.clone(),
+            params: self.params_block.clone(),
+            source: source.texture.clone().into_tuple(),
+            bloom: self.bloom[0].texture.clone().into_tuple(),
+            color: target.clone(),
+        });
+    }
+
+    /// Post-process each eye's layer of the source and write the result into
+    /// the eye's viewport (`clip`) of the display target, which must have the output
+    /// format of the chain.
+    pub fn apply<C: CommandBuffer<R>, T: format::RenderFormat>(
+        &mut self,
+        enc: &mut Encoder<R, C>,
+        source: &HdrTarget<R>,
+        target: &RenderTargetView<R, T>,
+        eyes: &[&EyeParams],
+    ) -> Result<(), Error> {
+        ensure!(T::get_format() == self.output_format,
+            "The post chain writes {:?}, not {:?}", self.output_format, T::get_format());
+        let target = target.raw();
+        let (w, h, ..) = target.get_dimensions();
+        for i in 0..eyes.len() {
+            self.resolve_layer(enc, source, i);
+        }
+
+        enc.clear(&self.bloom[0].target, [0.; 4]);
+        if self.auto_exposure.is_some() {
+            let mut rects = Vec::with_capacity(eyes.len());
+            for i in 0..eyes.len() {
+                let rect = Meter::<R>::rect(i, eyes.len());
+                enc.update_constant_buffer(&self.params_block, &self.params(rect, [0., 0.], i));
+                enc.draw(&self.quad.slice, &self.meter.pso, &meter::Data {
+                    verts: self.quad.buf.clone(),
+                    params: self.params_block.clone(),
+                    source: source.texture.clone().into_tuple(),
+                    color: self.meter.target.clone(),
+                });
+                rects.push(rect);
+            }
+            let download = self.meter.next_download();
+            let info = self.meter.texture.get_info().to_raw_image_info(ChannelType::Float, 0);
+            let copied = enc.copy_texture_to_buffer_raw(
+                self.meter.texture.raw(),
+                None,
+                info,
+                download.raw(),
+                0,
+            );
+            if let Err(e) = copied {
+                self.meter.free.push(download);
+                bail!("Could not read back luminance: {:?}", e);
+            }
+            self.meter.pending.push_back((download, rects));
+        }
+
+        for (i, eye) in eyes.iter().enumerate() {
+            self.output_layer(enc, source, target, i, unit_rect(eye, (w, h)));
+        }
+        Ok(())
+    }
+
+    /// Post-process a single layer of the source into the given viewport of the display
+    /// target, without metering. This is useful for showing one eye on another display.
+    /// The target must have the output format of the chain.
+    pub fn apply_layer<C: CommandBuffer<R>, T: format::RenderFormat>(
+        &self,
+        enc: &mut Encoder<R, C>,
+        source: &HdrTarget<R>,
+        target: &RenderTargetView<R, T>,
+        layer: usize,
+        viewport: Rect,
+    ) -> Result<(), Error> {
+        ensure!(T::get_format() == self.output_format,
+            "The post chain writes {:?}, not {:?}", self.output_format, T::get_format());
+        let target = target.raw();
+        let (w, h, ..) = target.get_dimensions();
+        self.resolve_layer(enc, source, layer);
+        enc.clear(&self.bloom[0].target, [0.; 4]);
+        let eye = EyeParams {
+            clip: viewport,
+            .. Default::default()
+        };
+        self.output_layer(enc, source, target, layer, unit_rect(&eye, (w, h)));
+        Ok(())
+    }
+}
+
+#[test]
+fn metering() {
+    let samples = [-2., -2., -2., -2., 0., 0., 0., 8.];
+    assert!(relative_eq!(meter_log_luminance(&samples, Metering::Average).unwrap(), 0.));
+    assert_eq!(meter_log_luminance(&[], Metering::Average), None);
+
+    // ignoring the darkest half and the brightest eighth leaves only the zeros
+    let hist = meter_log_luminance(&samples, Metering::Histogram { low: 0.5, high: 0.875 }).unwrap();
+    assert!(hist.abs() < (HISTOGRAM_RANGE.1 - HISTOGRAM_RANGE.0) / HISTOGRAM_BINS as f32);
+}
+
+#[test]
+fn exposure_adaptation() {
+    let cfg = AutoExposure {
+        brighten_speed: 1.,
+        darken_speed: 4.,
+        .. Default::default()
+    };
+    let target = cfg.key / 2.;
+
+    // converges on the exposure that maps the luminance onto the key value
+    let mut exposure = 1.;
+    for _ in 0..1000 {
+        exposure = adapt_exposure(exposure, 1., &cfg, 0.1);
+    }
+    assert!(relative_eq!(exposure, target, epsilon = 1e-4));
+
+    // darkening is faster than brightening, toward targets as far below as above the exposure
+    let key = cfg.key.log2();
+    let down = adapt_exposure(1., key + 2., &cfg, 0.1).log2();
+    let up = adapt_exposure(1., key - 2., &cfg, 0.1).log2();
+    assert!(down < 0. && up > 0.);
+    assert!(-down > up);
+
+    // clamped to the allowed range
+    assert!(adapt_exposure(1., 100., &cfg, 100.) >= cfg.min_exposure * 0.999);
+    assert!(adapt_exposure(1., -100., &cfg, 100.) <= cfg.max_exposure * 1.001);
+
+    // a minimum of 0 is allowed, even with no exposure yet
+    let cfg = AutoExposure { min_exposure: 0., .. cfg };
+    assert!(adapt_exposure(0., 1., &cfg, 0.1).is_finite());
+    assert!(adapt_exposure(0., 100., &cfg, 100.) >= 0.);
+}
@@ -0,0 +1,183 @@
+use gfx::{Resources, CommandBuffer};
+use nalgebra::{self as na, Point3};
+use std::mem;
+
+use super::{DrawParams, DrawTarget, PipelineKey};
+use ::Error;
+
+/// The passes of a `RenderQueue`, which are drawn in this order
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pass {
+    /// Opaque geometry, grouped by pipeline and material, then drawn front to back so
+    /// that hidden fragments fail the depth test early
+    Opaque,
+    /// Blended geometry (e.g. `LineStyle`), drawn back to front
+    Transparent,
+    /// Geometry drawn after everything else, grouped by pipeline and material. It is still
+    /// depth tested by the pipeline it is drawn with, so painters that should draw over
+    /// the scene need to be set up with `RasterOptions::depth` that always passes (e.g.
+    /// `gfx::preset::depth::PASS_TEST`)
+    Overlay,
+}
+
+/// Statistics of a submitted `RenderQueue`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// The draws recorded
+    pub recorded: usize,
+    /// The draw calls made, counting each target (see `DrawParams::targets`)
+    pub draws: usize,
+    /// The number of times the pipeline changed between draw calls
+    pub pipeline_switches: usize,
+    /// The number of times the material changed between draw calls
+    pub material_switches: usize,
+}
+
+/// The order of a recorded draw within the queue.
+type SortKey = (Pass, u64, PipelineKey, u64, u64);
+
+/// A value that sorts like the given distance.
+fn depth_bits(distance: f32) -> u64 {
+    // non-negative floats sort like their bits
+    distance.max(0.).to_bits() as u64
+}
+
+fn sort_key(pass: Pass, pipeline: PipelineKey, material: u64, distance: f32) -> SortKey {
+    let depth = depth_bits(distance);
+    match pass {
+        Pass::Opaque => (pass, 0, pipeline, material, depth),
+        Pass::Transparent => (pass, !depth, pipeline, material, 0),
+        Pass::Overlay => (pass, 0, pipeline, material, 0),
+    }
+}
+
+struct Item<'a, R: Resources, C: CommandBuffer<R>> {
+    pass: Pass,
+    pipeline: PipelineKey,
+    material: u64,
+    pos: Point3<f32>,
+    draw: Box<Fn(&mut DrawParams<R, C>, DrawTarget) -> Result<(), Error> + 'a>,
+}
+
+/// Collects the draws of a frame from any number of painters (see `Painter::queue`), so
+/// that they can be sorted to minimize state changes and drawn into each target in turn.
+/// Within each pass, draws are sorted by their pipeline and material, and by their
+/// distance from the first view.
+pub struct RenderQueue<'a, R: Resources, C: CommandBuffer<R>> {
+    items: Vec<Item<'a, R, C>>,
+}
+
+impl<'a, R: Resources, C: CommandBuffer<R>> RenderQueue<'a, R, C> {
+    /// Create an empty queue.
+    pub fn new() -> RenderQueue<'a, R, C> {
+        RenderQueue {
+            items: Vec::new(),
+        }
+    }
+
+    /// Record a draw. The pipeline and material identify the state that the draw shares
+    /// with others (the material e.g. by `Style::material_key`), and the position (in
+    /// world space) is used for depth sorting. `Painter::queue` records meshes this way.
+    pub fn record<D>(&mut self, pass: Pass, pipeline: PipelineKey, material: u64, pos: Point3<f32>, draw: D)
+        where D: Fn(&mut DrawParams<R, C>, DrawTarget) -> Result<(), Error> + 'a
+    {
+        self.items.push(Item {
+            pass: pass,
+            pipeline: pipeline,
+            material: material,
+            pos: pos,
+            draw: Box::new(draw),
+        });
+    }
+
+    /// The number of recorded draws.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Whether no draws have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Sort the recorded draws and draw them into each target, emptying the queue.
+    /// Returns `Err` at the first draw that fails.
+    pub fn try_submit(&mut self, ctx: &mut DrawParams<R, C>) -> Result<QueueStats, Error> {
+        self.submit_with(ctx, false)
+    }
+
+    /// Sort the recorded draws and draw them into each target, emptying the queue and
+    /// logging any errors.
+    pub fn submit(&mut self, ctx: &mut DrawParams<R, C>) -> QueueStats {
+        self.submit_with(ctx, true).unwrap_or_default()
+    }
+
+    fn submit_with(&mut self, ctx: &mut DrawParams<R, C>, log: bool) -> Result<QueueStats, Error> {
+        let eye = ctx.views.first().map(|v| v.eye.eye).unwrap_or(Point3::origin());
+        let mut items: Vec<_> = mem::replace(&mut self.items, Vec::new())
+            .into_iter()
+            .map(|i| (sort_key(i.pass, i.pipeline, i.material, na::distance(&eye, &i.pos)), i))
+            .collect();
+        // stable, so that equal draws keep the order they were recorded in
+        items.sort_by_key(|&(key, _)| key);
+
+        let mut stats = QueueStats {
+            recorded: items.len(),
+            .. Default::default()
+        };
+        for target in ctx.targets() {
+            let mut last = None;
+            for &(_, ref item) in &items {
+                match (item.draw)(ctx, target) {
+                    Err(ref e) if log => error!("{}", e),
+                    r => r?,
+                }
+                stats.draws += 1;
+                if last.map(|(p, _)| p) != Some(item.pipeline) {
+                    stats.pipeline_switches += 1;
+                }
+                if last.map(|(_, m)| m) != Some(item.material) {
+                    stats.material_switches += 1;
+                }
+                last = Some((item.pipeline, item.material));
+            }
+        }
+        Ok(stats)
+    }
+}
+
+impl<'a, R: Resources, C: CommandBuffer<R>> Default for RenderQueue<'a, R, C> {
+    fn default() -> RenderQueue<'a, R, C> { RenderQueue::new() }
+}
+
+#[test]
+fn sort_order() {
+    use super::{Features, ViewMode};
+
+    let pipeline = |owner| PipelineKey {
+        owner: owner,
+        prim: ::gfx::Primitive::TriangleList,
+        features: Features::none(),
+        mode: ViewMode::Single,
+    };
+    let sort_key = |pass, p, m, d| sort_key(pass, pipeline(p), m, d);
+    let mut keys = vec![
+        sort_key(Pass::Overlay, 1, 1, 0.),
+        sort_key(Pass::Transparent, 1, 1, 1.),
+        sort_key(Pass::Transparent, 2, 2, 5.),
+        sort_key(Pass::Opaque, 2, 1, 1.),
+        sort_key(Pass::Opaque, 1, 2, 1.),
+        sort_key(Pass::Opaque, 1, 1, 3.),
+        sort_key(Pass::Opaque, 1, 1, 2.),
+    ];
+    keys.sort();
+    assert_eq!(keys, vec![
+        sort_key(Pass::Opaque, 1, 1, 2.),
+        sort_key(Pass::Opaque, 1, 1, 3.),
+        sort_key(Pass::Opaque, 1, 2, 1.),
+        sort_key(Pass::Opaque, 2, 1, 1.),
+        sort_key(Pass::Transparent, 2, 2, 5.),
+        sort_key(Pass::Transparent, 1, 1, 1.),
+        sort_key(Pass::Overlay, 1, 1, 0.),
+    ]);
+}
@@ -3,15 +3,44 @@ macro_rules! static_file {
 }
 
 macro_rules! shader {
+    ($name:ident, $stereo:ident, features { vertex: $v:expr, fragment: $p:expr $(,)* }) => (
+        /// Build the shader with the given features defined.
+        pub fn $name<R: gfx::Resources, F: gfx::Factory<R>>(factory: &mut F, features: &[&str])
+            -> Result<::draw::shaders::Shaders<R>, Error> {
+            Ok(shader_set!(factory, vertex: $v.define_all(features), fragment: $p.define_all(features)))
+        }
+
+        /// Build the single-pass stereo variant of the shader for the given primitive,
+        /// with the given features defined.
+        pub fn $stereo<R: gfx::Resources, F: gfx::Factory<R>>(factory: &mut F, prim: gfx::Primitive, features: &[&str])
+            -> Result<::draw::shaders::Shaders<R>, Error> {
+            let v = $v.define("STEREO").define_all(features);
+            let g = ::draw::shaders::stereo_geometry(prim)?.defines_from(&v);
+            let p = $p.define("STEREO").define_all(features);
+            Ok(shader_set!(factory, vertex: v, geometry: g, fragment: p))
+        }
+    );
+    ($name:ident, $stereo:ident { vertex: $v:expr, fragment: $p:expr $(,)* }) => (
+        shader!($name { vertex: $v, fragment: $p });
+
+        /// Build the single-pass stereo variant of the shader for the given primitive.
+        pub fn $stereo<R: gfx::Resources, F: gfx::Factory<R>>(factory: &mut F, prim: gfx::Primitive)
+            -> Result<::draw::shaders::Shaders<R>, Error> {
+            let v = $v.define("STEREO");
+            let g = ::draw::shaders::stereo_geometry(prim)?.defines_from(&v);
+            let p = $p.define("STEREO");
+            Ok(shader_set!(factory, vertex: v, geometry: g, fragment: p))
+        }
+    );
     ($name:ident { $($x:tt)+ }) => (pub fn $name<R: gfx::Resources, F: gfx::Factory<R>>(factory: &mut F)
-        -> Result<gfx::ShaderSet<R>, Error> {
+        -> Result<::draw::shaders::Shaders<R>, Error> {
         Ok(shader_set!(factory, $($x)+))
     })
 }
 
 macro_rules! single_shader {
     ($f:ident, $c:ident, $s:expr) => ({
-        $f.$c(&$s.build().into_bytes())?
+        $s.compile(|src| $f.$c(src))?
     })
 }
 
@@ -19,45 +48,143 @@ macro_rules! shader_set {
     ($f:ident, vertex: $v:expr, fragment: $p:expr $(,)*) => ({
         let v = $v;
         let p = $p;
-        gfx::ShaderSet::Simple(
+        let set = gfx::ShaderSet::Simple(
             single_shader!($f, create_shader_vertex, v),
             single_shader!($f, create_shader_pixel, p),
-        )
+        );
+        ::draw::shaders::Shaders::new(set, &[&v, &p])
     });
     ($f:ident, vertex: $v:expr, geometry: $g:expr, fragment: $p:expr $(,)*) => ({
         let v = $v;
         let g = $g;
         let p = $p;
-        gfx::ShaderSet::Geometry(
+        let set = gfx::ShaderSet::Geometry(
             single_shader!($f, create_shader_vertex, v),
             single_shader!($f, create_shader_geometry, g),
             single_shader!($f, create_shader_pixel, p),
-        )
+        );
+        ::draw::shaders::Shaders::new(set, &[&v, &g, &p])
     });
     ($f:ident, vertex: $v:expr, tessellation_control: $h:expr, tessellation_evaluation: $d:expr, fragment: $p:expr $(,)*) => ({
         let v = $v;
         let h = $h;
         let d = $d;
         let p = $p;
-        gfx::ShaderSet::Tessellated(
+        let set = gfx::ShaderSet::Tessellated(
             single_shader!($f, create_shader_vertex, v),
             single_shader!($f, create_shader_hull, h),
             single_shader!($f, create_shader_domain, d),
             single_shader!($f, create_shader_pixel, p),
-        )
+        );
+        ::draw::shaders::Shaders::new(set, &[&v, &h, &d, &p])
     });
 }
 
+use gfx::{self, Primitive, PipelineStateError};
+use gfx::pso::{PipelineInit, PipelineState};
+use gfx::shade::core::CreateShaderError;
+use gfx::state::Rasterizer;
+use gfx::traits::FactoryExt;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Once, RwLock};
+
+use ::{Error, FlightError};
+
+/// The environment variable giving the initial runtime shader directory (see `set_shader_dir`)
+pub const SHADER_DIR_VAR: &str = "FLIGHT_SHADER_DIR";
+
+/// The number of source lines shown on each side of a line with a compile error
+const ERROR_CONTEXT: usize = 2;
+
+/// The files that shaders can `#include`, built into the library
+const INCLUDES: &[(&str, &str)] = &[
+    ("transform.glsl", include_str!("shaders/transform.glsl")),
+    ("stereo_eye.glsl", include_str!("shaders/stereo_eye.glsl")),
+    ("env_params.glsl", include_str!("shaders/env_params.glsl")),
+    ("post_params.glsl", include_str!("shaders/post_params.glsl")),
+    ("tone_map.glsl", include_str!("shaders/tone_map.glsl")),
+];
+
+/// Load shader sources from the given directory instead of the copies built into the
+/// library, so that shaders can be edited without recompiling. The directory mirrors
+/// `src/draw/shaders`; files that are missing from it fall back on the built-in copies.
+/// Until this is called, the directory is given by the `FLIGHT_SHADER_DIR` environment
+/// variable, if it is set. Only shaders built afterwards are affected.
+pub fn set_shader_dir<P: AsRef<Path>>(dir: Option<P>) {
+    let mut current = shader_dir_lock().write().unwrap_or_else(|e| e.into_inner());
+    *current = dir.map(|d| d.as_ref().to_owned());
+}
+
+/// The runtime shader directory, if one is set (see `set_shader_dir`).
+pub fn shader_dir() -> Option<PathBuf> {
+    shader_dir_lock().read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// The runtime shader directory, read from `FLIGHT_SHADER_DIR` when it is first used.
+fn shader_dir_lock() -> &'static RwLock<Option<PathBuf>> {
+    static INIT: Once = Once::new();
+    static mut DIR: *const RwLock<Option<PathBuf>> = 0 as *const _;
+    unsafe {
+        INIT.call_once(|| {
+            let dir = env::var_os(SHADER_DIR_VAR).map(PathBuf::from);
+            DIR = Box::into_raw(Box::new(RwLock::new(dir)));
+        });
+        &*DIR
+    }
+}
+
+/// Read a file from the runtime shader directory, if one is set and has the file.
+fn read_override(name: &str) -> Option<String> {
+    let path = shader_dir()?.join(Path::new(name).strip_prefix("shaders").unwrap_or(Path::new(name)));
+    match fs::read_to_string(&path) {
+        Ok(s) => Some(s),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("Could not read shader {}, using the built-in copy: {}", path.display(), e);
+            None
+        },
+    }
+}
+
+/// Parse an `#include "file"` line, returning the file name.
+fn include_name(line: &str) -> Result<Option<&str>, Error> {
+    let line = line.trim();
+    if !line.starts_with("#include") { return Ok(None) }
+    let name = line["#include".len()..].trim();
+    ensure!(name.len() >= 2 && name.starts_with('"') && name.ends_with('"'), "Malformed shader include: {}", line);
+    Ok(Some(&name[1..name.len() - 1]))
+}
+
+/// A shader source being built. Sources can `#include "file"` other files, which are found
+/// among the files added with `file`, in the runtime shader directory and among the files
+/// built into the library, in that order.
 pub struct BuildShader {
     prefix: String,
     source: String,
+    files: Vec<(String, String)>,
     pub name: String,
 }
 
+/// The source of a shader file: the copy in the runtime shader directory if there
+/// is one, otherwise the given built-in copy.
+pub fn read_source(name: &str, builtin: &str) -> String {
+    read_override(name).unwrap_or_else(|| builtin.to_owned())
+}
+
 pub fn source(name: &str, source: &str) -> BuildShader {
+    generated(name, read_source(name, source))
+}
+
+/// Start a shader from source generated at runtime, which the runtime shader
+/// directory does not replace.
+pub fn generated(name: &str, source: String) -> BuildShader {
     BuildShader {
         prefix: String::new(),
-        source: source.to_owned(),
+        source: source,
+        files: Vec::new(),
         name: name.to_owned(),
     }
 }
@@ -75,12 +202,251 @@ impl BuildShader {
         self
     }
 
-    pub fn build(self) -> String {
-        if self.source.starts_with("#version") {
+    /// Define each of the given names, e.g. the features of a shader variant.
+    pub fn define_all(self, names: &[&str]) -> BuildShader {
+        names.iter().fold(self, |s, n| s.define(n))
+    }
+
+    /// Copy the defines of another shader, so that this stage
+    /// agrees with it on the interface between them.
+    pub fn defines_from(mut self, other: &BuildShader) -> BuildShader {
+        self.prefix += &other.prefix;
+        self
+    }
+
+    /// Add a virtual file that the source can include.
+    pub fn file(mut self, name: &str, source: &str) -> BuildShader {
+        self.files.push((name.to_owned(), source.to_owned()));
+        self
+    }
+
+    /// Find the contents of an included file.
+    fn find_include(&self, name: &str) -> Option<String> {
+        self.files.iter()
+            .find(|f| f.0 == name)
+            .map(|f| f.1.clone())
+            .or_else(|| read_override(name))
+            .or_else(|| INCLUDES.iter().find(|f| f.0 == name).map(|f| f.1.to_owned()))
+    }
+
+    /// Replace the includes of a source with the included files. `#line` directives keep
+    /// the line numbers of each file, with a source string number (its index in `files`)
+    /// for each file. `stack` holds the files being included, to catch cycles.
+    fn expand(&self, source: &str, index: usize, files: &mut Vec<(String, String)>, stack: &mut Vec<String>)
+        -> Result<String, Error>
+    {
+        let mut out = String::new();
+        for (i, line) in source.lines().enumerate() {
+            match include_name(line)? {
+                Some(name) => {
+                    ensure!(!stack.iter().any(|s| s == name), "Shader file \"{}\" includes itself", name);
+                    let text = self.find_include(name)
+                        .ok_or_else(|| format_err!("Could not find shader file \"{}\" included by {}", name, files[index].0))?;
+                    let sub = files.len();
+                    files.push((name.to_owned(), text.clone()));
+                    stack.push(name.to_owned());
+                    out += &format!("#line 1 {}\n", sub);
+                    out += &self.expand(&text, sub, files, stack)?;
+                    stack.pop();
+                    out += &format!("#line {} {}\n", i + 2, index);
+                },
+                None => {
+                    out += line;
+                    out += "\n";
+                },
+            }
+        }
+        Ok(out)
+    }
+
+    /// Build the source, along with the name and contents of the file
+    /// for each source string number.
+    fn build_mapped(&self) -> Result<(String, Vec<(String, String)>), Error> {
+        let mut files = vec![(self.name.clone(), self.source.clone())];
+        let built = if self.source.starts_with("#version") {
             let (ver, src) = self.source.split_at(self.source.find('\n').unwrap_or(self.source.len()));
-            format!("{}\n{}#line 1\n{}", ver, self.prefix, src)
+            // the rest of the version line is line 1
+            let src = self.expand(src, 0, &mut files, &mut Vec::new())?;
+            format!("{}\n{}#line 1 0\n{}", ver, self.prefix, src)
         } else {
-            format!("{}#line 1\n{}", self.prefix, self.source)
+            let src = self.expand(&self.source, 0, &mut files, &mut Vec::new())?;
+            format!("{}#line 1 0\n{}", self.prefix, src)
+        };
+        Ok((built, files))
+    }
+
+    pub fn build(&self) -> Result<String, Error> {
+        self.build_mapped().map(|b| b.0)
+    }
+
+    /// Build the source and compile it with the given factory method. Compile errors
+    /// name the shader and its defines, and quote the failing lines of the original files.
+    pub fn compile<T, F>(&self, create: F) -> Result<T, Error>
+        where F: FnOnce(&[u8]) -> Result<T, CreateShaderError>
+    {
+        let (built, files) = self.build_mapped()?;
+        create(built.as_bytes()).map_err(|e| match e {
+            CreateShaderError::CompilationFailed(log) => FlightError::ShaderCompile {
+                name: self.name.clone(),
+                defines: self.defines(),
+                details: annotate(&log, &files),
+            }.into(),
+            e => e.into(),
+        })
+    }
+
+    /// A readable list of the defines, e.g. `STEREO, I_POS=v_pos`.
+    pub fn defines(&self) -> String {
+        let defines: Vec<_> = self.prefix.lines()
+            .map(|l| {
+                // skip the "#define"
+                let mut parts = l.splitn(3, ' ').skip(1);
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(val)) => format!("{}={}", name, val.trim()),
+                    (name, _) => name.unwrap_or("").to_owned(),
+                }
+            })
+            .collect();
+        if defines.is_empty() { "none".to_owned() } else { defines.join(", ") }
+    }
+}
+
+/// The compiled stages of a shader set, along with the names and defines of the shaders
+/// they were built from, so that link errors name them.
+pub struct Shaders<R: gfx::Resources> {
+    set: gfx::ShaderSet<R>,
+    stages: String,
+}
+
+impl<R: gfx::Resources> Shaders<R> {
+    pub fn new(set: gfx::ShaderSet<R>, stages: &[&BuildShader]) -> Shaders<R> {
+        Shaders {
+            set: set,
+            stages: stages.iter()
+                .map(|s| format!("{} (defines: {})", s.name, s.defines()))
+                .collect::<Vec<_>>()
+                .join(", "),
         }
     }
+
+    /// The compiled stages
+    pub fn set(&self) -> &gfx::ShaderSet<R> {
+        &self.set
+    }
+
+    /// Create a pipeline state from the shaders. Each pipeline state links its own
+    /// program, so link errors are reported here.
+    pub fn pipeline_state<F, I>(&self, f: &mut F, prim: Primitive, rast: Rasterizer, init: I)
+        -> Result<PipelineState<R, I::Meta>, Error>
+        where F: gfx::Factory<R>, I: PipelineInit
+    {
+        f.create_pipeline_state(&self.set, prim, rast, init).map_err(|e| match e {
+            PipelineStateError::Program(e) => FlightError::ShaderLink {
+                shaders: self.stages.clone(),
+                log: e.to_string(),
+            }.into(),
+            e => e.into(),
+        })
+    }
+}
+
+/// Find the source string number and line of a compiler log line, which drivers write as
+/// `0(12)`, `0:12(5):` or `0:12:`.
+fn error_location(line: &str) -> Option<(usize, usize)> {
+    let b = line.as_bytes();
+    let digits = |mut i: usize| {
+        let start = i;
+        while i < b.len() && b[i].is_ascii_digit() { i += 1 }
+        (i > start, i)
+    };
+    for start in 0..b.len() {
+        if !b[start].is_ascii_digit() { continue }
+        if start > 0 && (b[start - 1].is_ascii_alphanumeric() || b[start - 1] == b'_' || b[start - 1] == b'.') {
+            continue
+        }
+        let (_, i) = digits(start);
+        if i >= b.len() || (b[i] != b':' && b[i] != b'(') { continue }
+        let (found, j) = digits(i + 1);
+        if !found { continue }
+        let closed = match b[i] {
+            b'(' => j < b.len() && b[j] == b')',
+            _ => j < b.len() && (b[j] == b':' || b[j] == b'('),
+        };
+        if closed {
+            return Some((line[start..i].parse().ok()?, line[i + 1..j].parse().ok()?))
+        }
+    }
+    None
+}
+
+/// Follow each line of a compiler log that has a location with the
+/// lines around it in the file it refers to.
+fn annotate(log: &str, files: &[(String, String)]) -> String {
+    let mut out = String::new();
+    for entry in log.lines() {
+        out += entry;
+        out += "\n";
+        let (name, text, line) = match error_location(entry) {
+            Some((index, line)) if index < files.len() && line > 0 => (&files[index].0, &files[index].1, line),
+            _ => continue,
+        };
+        out += &format!("  --> {}:{}\n", name, line);
+        let first = line.saturating_sub(ERROR_CONTEXT).max(1);
+        for (n, l) in text.lines().enumerate().skip(first - 1).take(line + ERROR_CONTEXT + 1 - first) {
+            let mark = if n + 1 == line { ">" } else { " " };
+            out += &format!("  {} {:4} | {}\n", mark, n + 1, l);
+        }
+    }
+    out
+}
+
+/// The geometry shader that emits a primitive to both eye layers in single-pass stereo.
+pub fn stereo_geometry(prim: Primitive) -> Result<BuildShader, Error> {
+    let shader = static_file!("shaders/stereo.g.glsl");
+    Ok(match prim {
+        Primitive::PointList => shader.define("POINTS"),
+        Primitive::LineList | Primitive::LineStrip => shader.define("LINES"),
+        Primitive::TriangleList | Primitive::TriangleStrip => shader.define("TRIANGLES"),
+        _ => Err(FlightError::InvalidPrimitive { given: prim })?,
+    })
+}
+
+#[test]
+fn includes() {
+    let built = source("main.glsl", "#version 410\n#include \"a.glsl\"\nvoid main() {}\n")
+        .file("a.glsl", "#include \"b.glsl\"\nfloat a;\n")
+        .file("b.glsl", "float b;\n")
+        .define("X")
+        .build()
+        .unwrap();
+    assert_eq!(built, "#version 410\n#define X\n#line 1 0\n\n\
+        #line 1 1\n#line 1 2\nfloat b;\n#line 2 1\nfloat a;\n#line 3 0\nvoid main() {}\n");
+
+    assert!(source("main.glsl", "#include \"transform.glsl\"\n").build().is_ok());
+    assert!(source("main.glsl", "#include \"missing.glsl\"\n").build().is_err());
+    assert!(source("main.glsl", "#include \"a.glsl\"\n").file("a.glsl", "#include \"a.glsl\"\n").build().is_err());
+}
+
+#[test]
+fn compile_errors() {
+    assert_eq!(error_location("0(12) : error C0000: syntax error"), Some((0, 12)));
+    assert_eq!(error_location("1:7(3): error: `foo' undeclared"), Some((1, 7)));
+    assert_eq!(error_location("ERROR: 2:30: 'bar' : undeclared identifier"), Some((2, 30)));
+    assert_eq!(error_location("warning: version 410.0 is not supported"), None);
+
+    let shader = source("main.glsl", "#include \"a.glsl\"\nvoid main() {}\n")
+        .file("a.glsl", "float a;\nfloat b\nfloat c;\n")
+        .define("X")
+        .define_to("Y", "v_pos");
+    assert_eq!(shader.defines(), "X, Y=v_pos");
+    let (_, files) = shader.build_mapped().unwrap();
+    let details = annotate("1:2(1): error: syntax error\nother", &files);
+    assert_eq!(details.lines().collect::<Vec<_>>(), vec![
+        "1:2(1): error: syntax error",
+        "  --> a.glsl:2",
+        "       1 | float a;",
+        "  >    2 | float b",
+        "       3 | float c;",
+        "other",
+    ]);
 }
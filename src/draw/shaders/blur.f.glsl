@@ -0,0 +1,24 @@
+#version 410
+
+uniform sampler2D source;
+
+#include "post_params.glsl"
+
+in vec2 v_tex;
+out vec4 f_color;
+
+// 9-tap gaussian using linear filtering between texels
+const float offsets[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float weights[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    vec2 dir = blur_dir.xy * texel;
+
+    vec3 sum = texture(source, v_tex).rgb * weights[0];
+    for (int i = 1; i < 3; i++) {
+        sum += texture(source, v_tex + dir * offsets[i]).rgb * weights[i];
+        sum += texture(source, v_tex - dir * offsets[i]).rgb * weights[i];
+    }
+    f_color = vec4(sum, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 410
+
+uniform sampler2DArray source;
+
+#include "post_params.glsl"
+
+in vec2 v_tex;
+out vec4 f_color;
+
+void main() {
+    vec4 hdr = texture(source, vec3(v_tex, layer));
+    vec3 lum = hdr.rgb * exposure;
+    float peak = max(max(lum.r, lum.g), lum.b);
+    float excess = max(peak - bloom_threshold, 0.0);
+
+    f_color = vec4(lum * (excess / max(peak, 0.0001)), 1.0);
+}
@@ -3,15 +3,7 @@
 
 uniform samplerCube cube_map;
 
-layout(std140) uniform params {
-    mat4 sun_matrix;
-    vec4 sun_color;
-    float sun_in_env;
-    int radiance_levels;
-
-    float gamma;
-    float exposure;
-};
+#include "env_params.glsl"
 
 in vec3 I_POS;
 out vec4 f_color;
@@ -32,8 +24,5 @@ void main() {
 
     lum += sun_lum * smoothstep(edge, 1.0, sun_dot);
 
-    // hdr to ldr  
-    vec3 mapped = vec3(1.0) - exp(-lum * exposure);
-    mapped = pow(mapped, vec3(1.0 / gamma));
-    f_color = vec4(mapped, 1.0);
+    f_color = vec4(lum, 1.0);
 }
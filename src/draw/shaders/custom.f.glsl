@@ -0,0 +1,27 @@
+#version 410
+
+#include "transform.glsl"
+
+#include "stereo_eye.glsl"
+
+struct CustomParam {
+    vec4 value;
+};
+
+layout(std140) uniform custom {
+    CustomParam custom_params[CUSTOM_UNIFORMS];
+};
+
+in vec3 v_pos;
+in vec3 v_norm;
+in vec2 v_tex;
+
+out vec4 f_color;
+
+// The user's shading function, defined after this file along with the user's
+// uniforms and textures. The result is the HDR color, with alpha as opacity.
+vec4 shade();
+
+void main() {
+    f_color = shade();
+}
@@ -0,0 +1,8 @@
+layout(std140) uniform params {
+    mat4 sun_matrix;
+    vec4 sun_color;
+    float sun_in_env;
+    int radiance_levels;
+    float exposure;
+    int tone_map;
+};
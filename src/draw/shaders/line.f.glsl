@@ -0,0 +1,15 @@
+#version 410
+
+in vec3 v_color;
+noperspective in float v_dist;
+noperspective in float v_half;
+
+out vec4 f_color;
+
+void main() {
+    // how much of the pixel the line covers, fading out lines thinner than a pixel
+    float coverage = clamp(v_half + 0.5 - abs(v_dist), 0.0, 1.0) * min(2.0 * v_half, 1.0);
+
+    // unlit, and blended over the scene by its coverage (premultiplied)
+    f_color = vec4(v_color * coverage, coverage);
+}
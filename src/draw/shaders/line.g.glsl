@@ -0,0 +1,81 @@
+#version 410
+
+#include "transform.glsl"
+
+layout(std140) uniform line {
+    vec2 viewport;
+    float width;
+    int pixels;
+};
+
+layout(lines) in;
+#ifdef STEREO
+#define EYES 2
+layout(triangle_strip, max_vertices = 8) out;
+#else
+#define EYES 1
+layout(triangle_strip, max_vertices = 4) out;
+#endif
+
+in vec3 g_pos[];
+in vec3 g_color[];
+out vec3 v_pos;
+out vec3 v_color;
+noperspective out float v_dist;
+noperspective out float v_half;
+
+// Expand each line segment into a quad facing the camera, wide enough for
+// the line plus a one pixel border over which its edges fade out
+void main() {
+    vec2 half_size = 0.5 * viewport;
+    for (int eye = 0; eye < EYES; eye++) {
+        mat4 eye_proj = eye == 0 ? proj : right_proj;
+        mat4 view_proj = eye == 0 ? proj * view : right_proj * right_view;
+        vec4 p[2];
+        vec3 pos[2];
+        vec3 color[2];
+        for (int i = 0; i < 2; i++) {
+            p[i] = view_proj * gl_in[i].gl_Position;
+            pos[i] = g_pos[i];
+            color[i] = g_color[i];
+        }
+
+        // clip the segment to the near plane, so that both ends can be projected
+        float d0 = p[0].z + p[0].w;
+        float d1 = p[1].z + p[1].w;
+        if (d0 < 0.0 && d1 < 0.0) continue;
+        if (d0 < 0.0 || d1 < 0.0) {
+            int i = d0 < 0.0 ? 0 : 1;
+            float t = d0 / (d0 - d1);
+            p[i] = mix(p[0], p[1], t);
+            pos[i] = mix(pos[0], pos[1], t);
+            color[i] = mix(color[0], color[1], t);
+        }
+
+        vec2 dir = p[1].xy / p[1].w - p[0].xy / p[0].w;
+        dir *= half_size;
+        dir = length(dir) > 1e-6 ? normalize(dir) : vec2(1.0, 0.0);
+        vec2 normal = vec2(-dir.y, dir.x);
+
+        for (int i = 0; i < 2; i++) {
+            // half of the line's width in pixels at this end
+            float half_width = pixels != 0
+                ? 0.5 * width
+                : 0.5 * width * eye_proj[1][1] * half_size.y / p[i].w;
+            float extent = half_width + 1.0;
+            for (int side = -1; side <= 1; side += 2) {
+                vec2 offset = normal * float(side) * extent / half_size;
+                gl_Position = p[i] + vec4(offset * p[i].w, 0.0, 0.0);
+                #ifdef STEREO
+                gl_Layer = eye;
+                #endif
+                v_pos = pos[i];
+                v_color = color[i];
+                v_dist = float(side) * extent;
+                v_half = half_width;
+                EmitVertex();
+            }
+        }
+        EndPrimitive();
+    }
+}
@@ -0,0 +1,14 @@
+#version 410
+
+uniform sampler2DArray source;
+
+#include "post_params.glsl"
+
+in vec2 v_tex;
+out float f_lum;
+
+void main() {
+    vec3 hdr = texture(source, vec3(v_tex, layer)).rgb;
+    float lum = dot(hdr, vec3(0.2126, 0.7152, 0.0722));
+    f_lum = log2(max(lum, 0.0001));
+}
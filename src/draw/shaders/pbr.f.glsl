@@ -9,16 +9,18 @@ uniform sampler2D roughness_tex;
 
 layout(std140) uniform params {
     vec4 ambient;
-    // float gamma;
-    // float exposure;
 };
 
-layout(std140) uniform transform {
-    mat4 model;
-    mat4 view;
-    mat4 proj;
-    vec4 eye_pos;
-    float clip_offset;
+#include "transform.glsl"
+
+#include "stereo_eye.glsl"
+
+layout(std140) uniform material {
+    vec4 albedo_factor;
+    vec4 emissive_factor;
+    float metalness_factor;
+    float roughness_factor;
+    float normal_factor;
 };
 
 struct Light {
@@ -73,11 +75,12 @@ float geometrySmith(vec3 N, vec3 V, vec3 L, float roughness) {
 
 void main() {
     vec3 normal_map = texture(normal_tex, I_TEX).rgb * 2 - 1;
+    normal_map.xy *= normal_factor;
     vec3 norm = mat3(I_TAN, I_BITAN, I_NORM) * normal_map;
 
-    vec3 albedo = texture(albedo_tex, I_TEX).rgb;
-    float roughness = texture(roughness_tex, I_TEX).r;
-    float metalness = texture(metalness_tex, I_TEX).r;
+    vec3 albedo = texture(albedo_tex, I_TEX).rgb * albedo_factor.rgb;
+    float roughness = texture(roughness_tex, I_TEX).r * roughness_factor;
+    float metalness = texture(metalness_tex, I_TEX).r * metalness_factor;
 
     vec3 F0 = vec3(0.04);
     F0 = mix(F0, albedo, metalness);
@@ -115,6 +118,9 @@ void main() {
         lum += (kD * albedo / PI + brdf) * radiance * n_dot_l;
     }
 
+    // EMISSION
+    lum += emissive_factor.rgb * emissive_factor.a;
+
     // OUT
-    f_lum = vec4(pow(lum, vec3(1 / 2.2)), 1);
+    f_lum = vec4(lum, 1.0);
 }
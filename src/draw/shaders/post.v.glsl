@@ -0,0 +1,11 @@
+#version 410
+
+#include "post_params.glsl"
+
+in vec3 a_pos;
+out vec2 v_tex;
+
+void main() {
+    v_tex = a_pos.xy;
+    gl_Position = vec4(mix(rect.xy, rect.zw, a_pos.xy), 0.0, 1.0);
+}
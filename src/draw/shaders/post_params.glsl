@@ -0,0 +1,11 @@
+layout(std140) uniform params {
+    vec4 rect;
+    vec4 blur_dir;
+    float exposure;
+    float gamma;
+    int tone_map;
+    float bloom_strength;
+    float bloom_threshold;
+    int layer;
+    int srgb;
+};
@@ -0,0 +1,26 @@
+#version 410
+
+uniform sampler2DMSArray source;
+uniform int samples;
+
+#include "post_params.glsl"
+
+in vec2 v_tex;
+out vec4 f_color;
+
+void main() {
+    ivec3 texel = ivec3(ivec2(v_tex * vec2(textureSize(source).xy)), layer);
+
+    // weight samples by their inverse exposed brightness so that very bright
+    // samples do not overpower the edge once tone mapped
+    vec4 sum = vec4(0.0);
+    float total = 0.0;
+    for (int i = 0; i < samples; i++) {
+        vec4 hdr = texelFetch(source, texel, i);
+        float peak = max(max(hdr.r, hdr.g), hdr.b) * exposure;
+        float weight = 1.0 / (1.0 + peak);
+        sum += hdr * weight;
+        total += weight;
+    }
+    f_color = sum / total;
+}
@@ -6,5 +6,6 @@ in vec3 I_COLOR;
 out vec4 f_color;
 
 void main() {
-    f_color = vec4(pow(I_COLOR, vec3(1.0 / 2.2)), 1.0);
+    // unlit
+    f_color = vec4(I_COLOR, 1.0);
 }
@@ -0,0 +1,76 @@
+#version 410
+
+#include "transform.glsl"
+
+#if defined(POINTS)
+#define VERTS 1
+layout(points) in;
+layout(points, max_vertices = 2) out;
+#elif defined(LINES)
+#define VERTS 2
+layout(lines) in;
+layout(line_strip, max_vertices = 4) out;
+#else
+#define VERTS 3
+layout(triangles) in;
+layout(triangle_strip, max_vertices = 6) out;
+#endif
+
+in vec3 g_pos[];
+out vec3 v_pos;
+flat out vec4 v_eye_pos;
+
+#ifdef NORM
+in vec3 g_norm[];
+out vec3 v_norm;
+#endif
+
+#ifdef TEX
+in vec2 g_tex[];
+out vec2 v_tex;
+#endif
+
+#ifdef COLOR
+in vec3 g_color[];
+out vec3 v_color;
+#endif
+
+#ifdef TAN
+in vec3 g_tan[];
+out vec3 v_tan;
+in vec3 g_bitan[];
+out vec3 v_bitan;
+#endif
+
+// Emit each primitive once per eye, into the layer of that eye
+void main() {
+    for (int eye = 0; eye < 2; eye++) {
+        mat4 view_proj = eye == 0 ? proj * view : right_proj * right_view;
+        for (int i = 0; i < VERTS; i++) {
+            gl_Layer = eye;
+            gl_Position = view_proj * gl_in[i].gl_Position;
+            v_pos = g_pos[i];
+            v_eye_pos = eye == 0 ? eye_pos : right_eye_pos;
+
+            #ifdef NORM
+            v_norm = g_norm[i];
+            #endif
+
+            #ifdef TEX
+            v_tex = g_tex[i];
+            #endif
+
+            #ifdef COLOR
+            v_color = g_color[i];
+            #endif
+
+            #ifdef TAN
+            v_tan = g_tan[i];
+            v_bitan = g_bitan[i];
+            #endif
+
+            EmitVertex();
+        }
+        EndPrimitive();
+    }
+}
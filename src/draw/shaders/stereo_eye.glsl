@@ -0,0 +1,5 @@
+#ifdef STEREO
+// the eye position of the current layer, forwarded by the geometry shader
+flat in vec4 v_eye_pos;
+#define eye_pos v_eye_pos
+#endif
@@ -0,0 +1,18 @@
+#version 410
+
+uniform sampler2D albedo_tex;
+
+layout(std140) uniform material {
+    vec4 tint;
+};
+
+in vec2 I_TEX;
+
+out vec4 f_color;
+
+void main() {
+    vec4 albedo = texture(albedo_tex, I_TEX) * tint;
+
+    // unlit, and blended over the scene by its coverage (premultiplied)
+    f_color = vec4(albedo.rgb * albedo.a, albedo.a);
+}
@@ -0,0 +1,56 @@
+// The tone mapping operators of the post chain, selected by a `tone_map` uniform
+// (see `ToneMap::index`) that must be declared before this file is included
+
+const float UC2_A = 0.15;
+const float UC2_B = 0.50;
+const float UC2_C = 0.10;
+const float UC2_D = 0.20;
+const float UC2_E = 0.02;
+const float UC2_F = 0.30;
+const float UC2_W = 11.2;
+
+vec3 uncharted2_curve(vec3 x) {
+    return ((x * (UC2_A * x + UC2_C * UC2_B) + UC2_D * UC2_E) / (x * (UC2_A * x + UC2_B) + UC2_D * UC2_F)) - UC2_E / UC2_F;
+}
+
+vec3 tonemap(vec3 lum) {
+    if (tone_map == 1) {
+        // Reinhard
+        return lum / (vec3(1.0) + lum);
+    } else if (tone_map == 2) {
+        // ACES (Narkowicz fit)
+        return clamp((lum * (2.51 * lum + 0.03)) / (lum * (2.43 * lum + 0.59) + 0.14), 0.0, 1.0);
+    } else if (tone_map == 3) {
+        // Uncharted 2
+        return uncharted2_curve(lum * 2.0) / uncharted2_curve(vec3(UC2_W));
+    } else {
+        // exponential
+        return vec3(1.0) - exp(-lum);
+    }
+}
+
+// The luminance that `tonemap` maps to the given color. Colors are limited to just
+// below 1, which the operators only approach.
+vec3 inverse_tonemap(vec3 mapped) {
+    vec3 y = clamp(mapped, 0.0, 0.999);
+    if (tone_map == 1) {
+        // Reinhard
+        return y / (vec3(1.0) - y);
+    } else if (tone_map == 2) {
+        // ACES, the positive root of (2.51 - 2.43y) x^2 + (0.03 - 0.59y) x - 0.14y = 0
+        vec3 a = 2.51 - 2.43 * y;
+        vec3 b = 0.03 - 0.59 * y;
+        return (sqrt(b * b + 0.56 * a * y) - b) / (2.0 * a);
+    } else if (tone_map == 3) {
+        // Uncharted 2, the positive root of the curve's quadratic (in 2x) at the
+        // unnormalized, unshifted value u
+        vec3 u = y * uncharted2_curve(vec3(UC2_W)) + UC2_E / UC2_F;
+        vec3 a = UC2_A * (1.0 - u);
+        vec3 b = UC2_B * (UC2_C - u);
+        vec3 c = UC2_D * (UC2_E - u * UC2_F);
+        return (sqrt(b * b - 4.0 * a * c) - b) / (4.0 * a);
+    } else {
+        // exponential
+        return -log(vec3(1.0) - y);
+    }
+}
@@ -0,0 +1,25 @@
+#version 410
+
+uniform sampler2DArray source;
+uniform sampler2D bloom;
+
+#include "post_params.glsl"
+
+#include "tone_map.glsl"
+
+in vec2 v_tex;
+out vec4 f_color;
+
+void main() {
+    vec4 hdr = texture(source, vec3(v_tex, layer));
+    vec3 lum = hdr.rgb * exposure + texture(bloom, v_tex).rgb * bloom_strength;
+
+    // hdr to ldr
+    vec3 mapped = tonemap(lum);
+    // sRGB targets are gamma corrected by the hardware
+    if (srgb == 0) {
+        mapped = pow(mapped, vec3(1.0 / gamma));
+    }
+
+    f_color = vec4(mapped, 1.0);
+}
@@ -0,0 +1,9 @@
+layout(std140) uniform transform {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 eye_pos;
+    mat4 right_view;
+    mat4 right_proj;
+    vec4 right_eye_pos;
+};
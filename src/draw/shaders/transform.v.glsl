@@ -1,12 +1,20 @@
 #version 410
 
-layout(std140) uniform transform {
-    mat4 model;
-    mat4 view;
-    mat4 proj;
-    vec4 eye_pos;
-    float clip_offset;
-};
+#include "transform.glsl"
+
+#ifdef STEREO
+#define GEOMETRY
+#endif
+
+#ifdef GEOMETRY
+// outputs are projected and forwarded to the fragment shader by the geometry shader
+#define v_pos g_pos
+#define v_norm g_norm
+#define v_tex g_tex
+#define v_color g_color
+#define v_tan g_tan
+#define v_bitan g_bitan
+#endif
 
 in vec3 a_pos;
 out vec3 v_pos;
@@ -59,11 +67,9 @@ void main() {
     v_bitan = (model * vec4(a_bitan, 0)).xyz;
     #endif
 
-    vec4 c = proj * view * p;
-    // Fake an opengl viewport
-    // TODO: Submit a PR to GFX
-    c.x /= 2 * c.w;
-    c.x += clip_offset;
-    c.x *= c.w;
-    gl_Position = c;
+    #ifdef GEOMETRY
+    gl_Position = p;
+    #else
+    gl_Position = proj * view * p;
+    #endif
 }
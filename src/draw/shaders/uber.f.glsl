@@ -6,6 +6,7 @@ const float F0_REFLECTIVITY = 0.0337;
 uniform sampler2D normal_tex;
 uniform sampler2D albedo_tex;
 uniform sampler2D knobs_tex;
+uniform sampler2D emissive_tex;
 
 uniform samplerCube irradiance_map;
 uniform samplerCube radiance_map;
@@ -13,22 +14,21 @@ uniform sampler2D integrated_brdf_map;
 
 uniform sampler2DShadow shadow_depth;
 
-layout(std140) uniform transform {
-    mat4 model;
-    mat4 view;
-    mat4 proj;
-    vec4 eye_pos;
-    float clip_offset;
-};
+#include "transform.glsl"
+
+#include "stereo_eye.glsl"
 
-layout(std140) uniform params {
-    mat4 sun_matrix;
-    vec4 sun_color;
-    float sun_in_env;
-    int radiance_levels;
+#include "env_params.glsl"
 
-    float gamma;
-    float exposure;
+#include "tone_map.glsl"
+
+layout(std140) uniform material {
+    vec4 albedo_factor;
+    vec4 emissive_factor;
+    float metalness_factor;
+    float roughness_factor;
+    float flatness_factor;
+    float normal_factor;
 };
 
 in vec3 I_POS;
@@ -90,17 +90,23 @@ vec3 light_contrib(
 
 void main() {
     // normal mapping
+#ifdef NORMAL_MAP
     vec3 normal_map = texture(normal_tex, I_TEX).rgb * 2 - 1;
+    normal_map.xy *= normal_factor;
     vec3 norm = mat3(I_TAN, I_BITAN, I_NORM) * normal_map;
+#else
+    vec3 norm = I_NORM;
+#endif
 
     // material params
-    vec3 albedo = texture(albedo_tex, I_TEX).rgb;
-    vec3 knobs = texture(knobs_tex, I_TEX).rgb;
-    float metalness = knobs.r;
+    vec3 albedo = texture(albedo_tex, I_TEX).rgb * albedo_factor.rgb;
+    vec4 knobs = texture(knobs_tex, I_TEX);
+    float metalness = knobs.r * metalness_factor;
     metalness = sqrt(metalness);
-    float roughness = knobs.g;
+    float roughness = knobs.g * roughness_factor;
     float alpha = roughness * roughness;
-    float solidness = knobs.b;
+    float flatness = knobs.b * flatness_factor;
+    float occlusion = knobs.a;
 
     // imortant vectors
     vec3 N = normalize(norm);
@@ -112,12 +118,12 @@ void main() {
     // outgoing radiance
     vec3 lum = vec3(0.0);
 
-    // IBL
+    // IBL (occluded by the baked ambient occlusion)
     // indirect diffuse
-    lum += texture(irradiance_map, N).rgb * albedo * (1.0 - metalness);
+    lum += texture(irradiance_map, N).rgb * albedo * (1.0 - metalness) * occlusion;
     vec2 env_brdf = texture(integrated_brdf_map, vec2(NdotV, roughness)).rg;
     float lod = mix(0, radiance_levels - 1, roughness);
-    lum += textureLod(radiance_map, R, lod).rgb * (albedo * env_brdf.r + vec3(env_brdf.g));
+    lum += textureLod(radiance_map, R, lod).rgb * (albedo * env_brdf.r + vec3(env_brdf.g)) * occlusion;
 
     // sun shadow
     vec4 sun_frag_pos = sun_matrix * vec4(I_POS, 1.0);
@@ -141,10 +147,17 @@ void main() {
         max(alpha, 0.0025),
         metalness);
 
-    // hdr to ldr  
-    vec3 mapped = vec3(1.0) - exp(-lum * exposure);
-    //mapped = mix(mapped, albedo, solidness); // make solid
-    mapped = pow(mapped, vec3(1.0 / gamma));
+    // emission
+#ifdef EMISSIVE
+    lum += texture(emissive_tex, I_TEX).rgb * emissive_factor.rgb * emissive_factor.a;
+#endif
+
+    // make flat: blend the albedo over the luminance as the post chain will tone map
+    // it, and find the luminance that the post chain maps to the result
+    if (flatness > 0.0) {
+        float e = max(exposure, 1e-6);
+        lum = inverse_tonemap(mix(tonemap(lum * e), albedo, flatness)) / e;
+    }
 
-    f_color = vec4(mapped, 1.0);
+    f_color = vec4(lum, 1.0);
 }
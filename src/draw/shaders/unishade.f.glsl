@@ -12,6 +12,6 @@ out vec4 f_color;
 
 void main() {
     float lightness = max(dot(vec3(0, 1, 0), I_NORM), 0);
-    f_color = mix(dark, light, lightness);
-    f_color.xyz = pow(f_color.xyz, vec3(1 / 2.2));
+    // unlit
+    f_color = vec4(mix(dark, light, lightness).rgb, 1.0);
 }
\ No newline at end of file
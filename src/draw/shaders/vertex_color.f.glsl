@@ -0,0 +1,25 @@
+#version 410
+
+layout(std140) uniform lighting {
+    vec4 sky;
+    vec4 ground;
+    vec4 sun_dir;
+    vec4 sun_color;
+};
+
+in vec3 I_POS;
+in vec3 I_NORM;
+in vec3 I_COLOR;
+
+out vec4 f_lum;
+
+void main() {
+    vec3 n = normalize(I_NORM);
+
+    // hemisphere ambient light, fading from the ground color below to the sky color above
+    vec3 light = mix(ground.rgb * ground.a, sky.rgb * sky.a, 0.5 * n.y + 0.5);
+    // directional sun light
+    light += max(dot(n, -normalize(sun_dir.xyz)), 0.0) * sun_color.rgb * sun_color.a;
+
+    f_lum = vec4(I_COLOR * light, 1.0);
+}
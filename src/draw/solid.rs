@@ -1,24 +1,24 @@
-use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Slice, Encoder};
 use gfx::pso::PipelineState;
 use gfx::traits::FactoryExt;
 use gfx::handle::Buffer;
-use gfx::state::Rasterizer;
+use std::mem;
 
-use super::{StyleInputs, Style, TransformBlock};
+use super::{StyleInputs, Style, RasterOptions, Features, ViewMode, TransformBlock, RawDepthTarget, EyeTarget, color_target, depth_target, BlockBuffer};
+use super::shaders::Shaders;
 use ::mesh::{Primitive, VertC};
-use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef};
+use ::Error;
 
 gfx_defines!{
     pipeline pl {
         verts: gfx::VertexBuffer<VertC> = (),
         transform: gfx::ConstantBuffer<TransformBlock> = "transform",
-        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
-        color: gfx::RenderTarget<ColorFormat> = "f_color",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::LESS_EQUAL_WRITE,
+        color: gfx::RawRenderTarget = color_target("f_color", None),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::LESS_EQUAL_WRITE),
     }
 }
 
-shader!(shader {
+shader!(shader, stereo_shader {
     vertex: static_file!("shaders/transform.v.glsl")
         .define("COLOR"),
     fragment: static_file!("shaders/simple.f.glsl")
@@ -28,19 +28,36 @@ shader!(shader {
 
 /// The configuration for solid color rendering
 pub struct SolidInputs<R: Resources> {
-    shaders: ShaderSet<R>,
+    shaders: Shaders<R>,
     transform: Option<TransformBlock>,
-    transform_block: Buffer<R, TransformBlock>,
+    transform_block: BlockBuffer<R, TransformBlock>,
+    depth_test: bool,
+}
+
+impl<R: Resources> SolidInputs<R> {
+    /// Enable or disable depth testing. When disabled, objects are drawn over
+    /// everything already drawn, and do not write depth.
+    pub fn depth_test(&mut self, test: bool) {
+        self.depth_test = test;
+    }
 }
 
 impl<R: Resources> StyleInputs<R> for SolidInputs<R> {
+    type Shaders = Shaders<R>;
     fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
-    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+    fn shader_set(&self) -> &ShaderSet<R> { self.shaders.set() }
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Shaders<R>, Error> {
+        shader(f)
+    }
+    fn swap_shaders(&mut self, shaders: Shaders<R>) -> Shaders<R> {
+        mem::replace(&mut self.shaders, shaders)
+    }
 }
 
 /// Draws objects in solid colors (without lighting) using the per-vertex color attribute
 pub struct SolidStyle<R: Resources> {
     pso: PipelineState<R, pl::Meta>,
+    overlay_pso: PipelineState<R, pl::Meta>,
 }
 
 impl<R: Resources> Style<R> for SolidStyle<R> {
@@ -52,10 +69,25 @@ impl<R: Resources> Style<R> for SolidStyle<R> {
         f: &mut F,
         i: &mut SolidInputs<R>,
         p: Primitive,
-        r: Rasterizer,
+        o: &RasterOptions,
+        _: Features,
+        mode: ViewMode,
     ) -> Result<Self, Error> {
+        let stereo;
+        let shaders = match mode {
+            ViewMode::Single => &i.shaders,
+            ViewMode::Stereo => {
+                stereo = stereo_shader(f, p)?;
+                &stereo
+            },
+        };
+        let overlay = pl::Init {
+            depth: (o.formats.depth, gfx::preset::depth::PASS_TEST),
+            .. pipeline_init!(pl, o)
+        };
         Ok(SolidStyle {
-            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+            pso: shaders.pipeline_state(f, p, o.rasterizer(), pipeline_init!(pl, o))?,
+            overlay_pso: shaders.pipeline_state(f, p, o.rasterizer(), overlay)?,
         })
     }
 
@@ -65,7 +97,8 @@ impl<R: Resources> Style<R> for SolidStyle<R> {
         Ok(SolidInputs {
             shaders: shader(f)?,
             transform: None,
-            transform_block: f.create_constant_buffer(1),
+            transform_block: BlockBuffer::new(f),
+            depth_test: true,
         })
     }
 
@@ -73,26 +106,25 @@ impl<R: Resources> Style<R> for SolidStyle<R> {
         &self,
         inputs: &mut SolidInputs<R>,
         enc: &mut Encoder<R, C>,
-        color: TargetRef<R>,
-        depth: DepthRef<R>,
-        scissor: Rect,
+        target: &EyeTarget<R>,
         slice: &Slice<R>,
         buf: Buffer<R, Self::Vertex>,
         _: &(),
     )
-        -> Result<(), Error>
+        -> Result<usize, Error>
         where C: CommandBuffer<R>
     {
+        let mut updates = 0;
         if let Some(t) = inputs.transform.take() {
-            enc.update_constant_buffer(&inputs.transform_block, &t);
+            updates += inputs.transform_block.update(enc, &t);
         }
-        enc.draw(slice, &self.pso, &pl::Data {
-            color: color,
-            depth: depth,
+        let pso = if inputs.depth_test { &self.pso } else { &self.overlay_pso };
+        enc.draw(slice, pso, &pl::Data {
+            color: target.color.clone(),
+            depth: target.depth.clone(),
             verts: buf,
-            scissor: scissor,
-            transform: inputs.transform_block.clone(),
+            transform: inputs.transform_block.buffer().clone(),
         });
-        Ok(())
+        Ok(updates)
     }
 }
\ No newline at end of file
@@ -0,0 +1,159 @@
+use gfx::{Resources, CommandBuffer, Factory, Encoder, Rect};
+use gfx::traits::FactoryExt;
+use nalgebra::{Isometry3, Point3, Vector3, UnitQuaternion, Translation3};
+
+use super::{EyeParams, View, PostChain, HdrTarget};
+use ::{Error, TargetRef};
+
+/// What a `Spectator` shows
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpectatorMode {
+    /// A camera that follows the head, looking out from the head's point of view
+    HeadFollow,
+    /// A camera that follows the head from the given offset. The offset is relative to
+    /// the direction the head faces, ignoring pitch and roll (e.g. `[0, 0.3, 1.5]` is
+    /// slightly above and behind the head).
+    ThirdPerson {
+        offset: Vector3<f32>,
+    },
+    /// The image of one of the HMD's eyes (by index), letterboxed to fit the display
+    Eye(usize),
+}
+
+/// A desktop view for bystanders, drawn alongside the HMD views. Camera modes add an extra
+/// view to the `DrawParams` (see `view`), so the scene is drawn for it in the same frame.
+pub struct Spectator<R: Resources> {
+    /// What is shown
+    pub mode: SpectatorMode,
+    /// Vertical field of view of the camera modes (radians)
+    pub fov: f32,
+    /// Time (seconds) over which the camera catches up with the head; 0 disables smoothing
+    pub smoothing: f32,
+    /// Near clipping plane of the camera
+    pub near: f32,
+    /// Far clipping plane of the camera
+    pub far: f32,
+    /// The post-processing applied to the spectator image
+    pub post: PostChain<R>,
+    hdr: HdrTarget<R>,
+    pose: Option<Isometry3<f32>>,
+}
+
+/// The camera pose that the given mode aims for.
+fn camera_target(mode: SpectatorMode, head: &Isometry3<f32>) -> Option<Isometry3<f32>> {
+    match mode {
+        SpectatorMode::HeadFollow => Some(*head),
+        SpectatorMode::ThirdPerson { offset } => {
+            let fwd = head.rotation * -Vector3::z();
+            let yaw = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), (-fwd.x).atan2(-fwd.z));
+            let target = Point3::from_coordinates(head.translation.vector);
+            let eye = target + yaw * offset;
+            if (eye - target).norm() < 1e-6 { return Some(*head) }
+            Some(Isometry3::look_at_rh(&eye, &target, &Vector3::y()).inverse())
+        },
+        SpectatorMode::Eye(_) => None,
+    }
+}
+
+/// Move a pose toward another, catching up over roughly `smoothing` seconds.
+fn smooth_pose(from: &Isometry3<f32>, to: &Isometry3<f32>, smoothing: f32, dt: f32) -> Isometry3<f32> {
+    if smoothing <= 0. { return *to }
+    let t = 1. - (-dt.max(0.) / smoothing).exp();
+    let a = from.translation.vector;
+    let b = to.translation.vector;
+    Isometry3::from_parts(
+        Translation3::from_vector(a + (b - a) * t),
+        from.rotation.try_slerp(&to.rotation, t, 1e-6).unwrap_or(to.rotation),
+    )
+}
+
+/// Fit a rectangle with the given aspect ratio (width / height) in the middle of an area.
+fn letterbox(aspect: f32, width: u16, height: u16) -> Rect {
+    let area = width as f32 / height.max(1) as f32;
+    if aspect > area {
+        let h = (width as f32 / aspect) as u16;
+        Rect { x: 0, y: (height - h.min(height)) / 2, w: width, h: h }
+    } else {
+        let w = (height as f32 * aspect) as u16;
+        Rect { x: (width - w.min(width)) / 2, y: 0, w: w, h: height }
+    }
+}
+
+impl<R: Resources> Spectator<R> {
+    /// Create a spectator for a display of the given size.
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F, width: u16, height: u16, samples: u8)
+        -> Result<Spectator<R>, Error>
+    {
+        Ok(Spectator {
+            mode: SpectatorMode::ThirdPerson { offset: Vector3::new(0., 0.3, 1.5) },
+            fov: 1.,
+            smoothing: 0.3,
+            near: 0.1,
+            far: 100.,
+            post: PostChain::new(f, width, height)?,
+            hdr: HdrTarget::new_multisampled(f, width, height, 1, samples)?,
+            pose: None,
+        })
+    }
+
+    /// Follow the head to the given pose, `dt` seconds after the last update.
+    pub fn update(&mut self, head: &Isometry3<f32>, dt: f32) {
+        self.pose = camera_target(self.mode, head).map(|target| match self.pose {
+            Some(ref pose) => smooth_pose(pose, &target, self.smoothing, dt),
+            None => target,
+        });
+    }
+
+    /// The parameters of the spectator camera, or `None` if there is no camera (yet).
+    pub fn eye(&self) -> Option<EyeParams> {
+        let (w, h) = self.hdr.size;
+        self.pose.map(|pose| EyeParams::from_camera_viewport(
+            pose,
+            self.fov,
+            self.near,
+            self.far,
+            Rect { x: 0, y: 0, w: w, h: h },
+        ))
+    }
+
+    /// The view to add to the draw parameters in camera modes.
+    pub fn view(&self) -> Option<View<R>> {
+        match self.mode {
+            SpectatorMode::Eye(_) => None,
+            _ => self.eye().map(|eye| View {
+                eye: eye,
+                target: self.hdr.eyes[0].clone(),
+            }),
+        }
+    }
+
+    /// Show the spectator image on the display target. `hmd` is the target the HMD
+    /// views were drawn into, used by `SpectatorMode::Eye`.
+    pub fn present<C: CommandBuffer<R>>(
+        &mut self,
+        enc: &mut Encoder<R, C>,
+        hmd: &HdrTarget<R>,
+        display: &TargetRef<R>,
+    ) -> Result<(), Error> {
+        match self.mode {
+            SpectatorMode::Eye(i) => {
+                ensure!(i < hmd.eyes.len(), "There is no eye {} to show", i);
+                let (w, h, ..) = display.get_dimensions();
+                let aspect = hmd.size.0 as f32 / hmd.size.1 as f32;
+                enc.clear(display, [0., 0., 0., 1.]);
+                self.post.apply_layer(enc, hmd, display, i, letterbox(aspect, w, h))
+            },
+            _ => match self.eye() {
+                Some(eye) => self.post.apply(enc, &self.hdr, display, &[&eye]),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+#[test]
+fn letterboxing() {
+    assert_eq!(letterbox(2., 100, 100), Rect { x: 0, y: 25, w: 100, h: 50 });
+    assert_eq!(letterbox(0.5, 100, 100), Rect { x: 25, y: 0, w: 50, h: 100 });
+    assert_eq!(letterbox(1., 200, 100), Rect { x: 50, y: 0, w: 100, h: 100 });
+}
@@ -0,0 +1,151 @@
+use gfx::{Resources, Primitive, Slice, CommandBuffer, Encoder};
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::atomic::{self, AtomicUsize};
+use std::time::Duration;
+
+use super::{Features, ViewMode};
+
+/// Identifies a pipeline, for counting pipeline switches in `FrameStats` and grouping
+/// draws in `RenderQueue`: the owner of the pipeline (e.g. a painter) and the variant of
+/// the owner's pipelines that it is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    /// The owner of the pipeline (see `PipelineKey::new_owner`)
+    pub owner: usize,
+    /// The primitive drawn
+    pub prim: Primitive,
+    /// The features of the variant
+    pub features: Features,
+    /// The view mode of the variant
+    pub mode: ViewMode,
+}
+
+impl PipelineKey {
+    /// A new owner of pipelines, which differs from all earlier ones.
+    pub fn new_owner() -> usize {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        NEXT.fetch_add(1, atomic::Ordering::Relaxed)
+    }
+
+    fn order(&self) -> (usize, u8, u32, bool) {
+        (self.owner, self.prim as u8, self.features.0, self.mode == ViewMode::Stereo)
+    }
+}
+
+impl PartialOrd for PipelineKey {
+    fn partial_cmp(&self, other: &PipelineKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PipelineKey {
+    fn cmp(&self, other: &PipelineKey) -> Ordering {
+        self.order().cmp(&other.order())
+    }
+}
+
+/// The time spent in a pass of a `RenderGraph` (or of `DrawParams::pass`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct PassTime {
+    /// The name of the pass
+    pub name: String,
+    /// The time spent encoding the pass on the CPU
+    pub cpu: Duration,
+    /// The time the GPU spent on the pass, if it was timed by a `GpuTimer`. Timers read
+    /// their results back without stalling, so this is from an earlier frame.
+    pub gpu: Option<Duration>,
+}
+
+/// Times passes on the GPU, for `PassTime::gpu` (see `Frame::timer` and `DrawParams::pass`).
+/// gfx does not expose timer queries, so this is implemented for a backend, e.g. with
+/// `GL_TIME_ELAPSED` queries through `gfx_device_gl::Device::with_gl`. The commands of a
+/// pass are recorded into the encoder, so implementations flush it before starting and
+/// stopping a timer.
+pub trait GpuTimer<R: Resources, C: CommandBuffer<R>> {
+    /// Start timing the pass with the given name.
+    fn begin(&mut self, enc: &mut Encoder<R, C>, pass: &str);
+    /// Stop timing the pass with the given name, returning the last time that has been
+    /// read back for it, if any.
+    fn end(&mut self, enc: &mut Encoder<R, C>, pass: &str) -> Option<Duration>;
+}
+
+/// Rendering statistics of a frame, collected in `DrawParams::stats` by painters, the
+/// background of `UberStyle` and the frames of a `RenderGraph`. Read and reset them once
+/// per frame with `DrawParams::take_stats`. They are summarized in a single line by
+/// `Display`, for logging, and `DebugDraw::frame_stats` draws the pass times in the scene.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// The draw calls made
+    pub draws: usize,
+    /// The triangles submitted (lines and points are not counted). Single-pass stereo
+    /// draws count the triangles of both layers, as drawing the views separately does.
+    pub triangles: u64,
+    /// The constant buffers updated before draw calls
+    pub buffer_updates: usize,
+    /// The number of times the pipeline changed between draw calls
+    pub pipeline_switches: usize,
+    /// The passes run by a `RenderGraph` (or `DrawParams::pass`), in order
+    pub passes: Vec<PassTime>,
+    last_pipeline: Option<PipelineKey>,
+}
+
+impl FrameStats {
+    /// Count a draw call of a slice with the given pipeline, after the given number of
+    /// constant buffer updates.
+    pub fn draw<R: Resources>(&mut self, pipeline: PipelineKey, prim: Primitive, slice: &Slice<R>, updates: usize) {
+        let instances = slice.instances.map(|(n, _)| n as u64).unwrap_or(1);
+        let layers = if pipeline.mode == ViewMode::Stereo { 2 } else { 1 };
+        self.draws += 1;
+        self.triangles += triangle_count(prim, slice.end.saturating_sub(slice.start) as u64) * instances * layers;
+        self.buffer_updates += updates;
+        if self.last_pipeline != Some(pipeline) {
+            self.pipeline_switches += 1;
+            self.last_pipeline = Some(pipeline);
+        }
+    }
+
+    /// The total time spent encoding the passes.
+    pub fn pass_time(&self) -> Duration {
+        self.passes.iter().fold(Duration::from_secs(0), |t, p| t + p.cpu)
+    }
+}
+
+/// Milliseconds in a duration, for display.
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1e3 + d.subsec_nanos() as f64 * 1e-6
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} draws, {} triangles, {} buffer updates, {} pipeline switches",
+            self.draws, self.triangles, self.buffer_updates, self.pipeline_switches)?;
+        for p in &self.passes {
+            write!(f, ", {} {:.2} ms encoding", p.name, millis(p.cpu))?;
+            if let Some(gpu) = p.gpu {
+                write!(f, " {:.2} ms GPU", millis(gpu))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of triangles drawn from the given number of vertices (or indices).
+fn triangle_count(prim: Primitive, verts: u64) -> u64 {
+    match prim {
+        Primitive::TriangleList => verts / 3,
+        Primitive::TriangleStrip => verts.saturating_sub(2),
+        Primitive::TriangleListAdjacency => verts / 6,
+        Primitive::TriangleStripAdjacency => verts.saturating_sub(4) / 2,
+        _ => 0,
+    }
+}
+
+#[test]
+fn triangles() {
+    assert_eq!(triangle_count(Primitive::TriangleList, 36), 12);
+    assert_eq!(triangle_count(Primitive::TriangleStrip, 4), 2);
+    assert_eq!(triangle_count(Primitive::TriangleStrip, 1), 0);
+    assert_eq!(triangle_count(Primitive::TriangleStripAdjacency, 8), 2);
+    assert_eq!(triangle_count(Primitive::LineList, 36), 0);
+}
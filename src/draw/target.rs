@@ -0,0 +1,79 @@
+use gfx::Resources;
+use gfx::format::{self, Format};
+use gfx::handle::{RawDepthStencilView, Manager};
+use gfx::pso::{DataLink, DataBind, RawDataSet, AccessInfo};
+use gfx::state::Depth;
+use gfx_core::pso::DepthStencilDesc;
+
+use ::{HdrColorFormat, DepthFormat};
+
+/// The initializer of the pipeline `$pl` of a style (declared with `gfx_defines!`, with
+/// `color` and `depth` targets), in the target formats and with the depth state of the
+/// given `RasterOptions`, and with the pipeline's own initializer otherwise.
+macro_rules! pipeline_init {
+    ($pl:ident, $o:expr) => ({
+        let o: &::draw::RasterOptions = $o;
+        $pl::Init {
+            color: o.color_target($pl::new().color),
+            depth: o.depth_target($pl::new().depth),
+            .. $pl::new()
+        }
+    })
+}
+
+/// The pixel formats of the color and depth targets that the pipelines of a painter draw
+/// into (see `RasterOptions::formats` and `EyeTarget`). The defaults match `HdrTarget`.
+/// Other formats let painters draw straight into e.g. sRGB targets, which encode colors
+/// in hardware, or `D32` depth buffers (see `HdrTarget::with_depth`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TargetFormats {
+    /// The format of the color target
+    pub color: Format,
+    /// The format of the depth target
+    pub depth: Format,
+}
+
+impl TargetFormats {
+    /// The formats of the given color and depth formats, for example
+    /// `TargetFormats::of::<(R8_G8_B8_A8, Srgb), (D32, Float)>()`.
+    pub fn of<C: format::RenderFormat, D: format::DepthFormat>() -> TargetFormats {
+        TargetFormats {
+            color: C::get_format(),
+            depth: D::get_format(),
+        }
+    }
+}
+
+impl Default for TargetFormats {
+    fn default() -> TargetFormats {
+        TargetFormats::of::<HdrColorFormat, DepthFormat>()
+    }
+}
+
+/// A depth target pipeline component whose format is given when the pipeline state is
+/// created, like `gfx::RawRenderTarget` is for color targets.
+///
+/// - init: `(Format, Depth)`
+/// - data: `RawDepthStencilView`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RawDepthTarget;
+
+impl<'a> DataLink<'a> for RawDepthTarget {
+    type Init = (Format, Depth);
+
+    fn new() -> Self { RawDepthTarget }
+
+    fn is_active(&self) -> bool { true }
+
+    fn link_depth_stencil(&mut self, init: &Self::Init) -> Option<DepthStencilDesc> {
+        Some((init.0, init.1.into()))
+    }
+}
+
+impl<R: Resources> DataBind<R> for RawDepthTarget {
+    type Data = RawDepthStencilView<R>;
+
+    fn bind_to(&self, out: &mut RawDataSet<R>, data: &Self::Data, man: &mut Manager<R>, _: &mut AccessInfo<R>) {
+        out.pixel_targets.add_depth_stencil(man.ref_dsv(data), true, false, data.get_dimensions());
+    }
+}
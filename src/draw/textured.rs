@@ -0,0 +1,155 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use gfx::format::*;
+use std::mem;
+
+use super::{StyleInputs, Style, RasterOptions, Features, ViewMode, TransformBlock, DefaultTextures, or_default, RawDepthTarget, EyeTarget, PREMULTIPLIED, color_target, depth_target, BlockBuffer, MaterialKey};
+use super::shaders::Shaders;
+use ::mesh::{Primitive, VertNT};
+use ::{Error, Texture};
+
+/// The texture and tint of meshes drawn by `TexturedStyle`. A missing albedo map is
+/// replaced by white, so that the mesh is drawn in the tint color. The alpha of the
+/// albedo map and the tint is the coverage of the mesh, which is blended over the scene.
+#[derive(Clone)]
+pub struct TexturedMaterial<R: Resources> {
+    /// Albedo map (the displayed color)
+    pub albedo: Option<Texture<R, (R8_G8_B8_A8, Srgb)>>,
+    /// Linear color (and alpha) multiplied with the albedo map
+    pub tint: [f32; 4],
+}
+
+impl<R: Resources> Default for TexturedMaterial<R> {
+    fn default() -> TexturedMaterial<R> {
+        TexturedMaterial {
+            albedo: None,
+            tint: [1.; 4],
+        }
+    }
+}
+
+gfx_defines!{
+    constant TexturedBlock {
+        tint: [f32; 4] = "tint",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        material: gfx::ConstantBuffer<TexturedBlock> = "material",
+        albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
+        color: gfx::RawRenderTarget = color_target("f_color", Some(PREMULTIPLIED)),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::LESS_EQUAL_WRITE),
+    }
+}
+
+shader!(shader, stereo_shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("TEX"),
+    fragment: static_file!("shaders/textured.f.glsl")
+        .define_to("I_TEX", "v_tex")
+});
+
+/// The configuration for textured rendering
+pub struct TexturedInputs<R: Resources> {
+    shaders: Shaders<R>,
+    transform: Option<TransformBlock>,
+    transform_block: BlockBuffer<R, TransformBlock>,
+    material_block: BlockBuffer<R, TexturedBlock>,
+    defaults: DefaultTextures<R>,
+}
+
+impl<R: Resources> StyleInputs<R> for TexturedInputs<R> {
+    type Shaders = Shaders<R>;
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { self.shaders.set() }
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Shaders<R>, Error> {
+        shader(f)
+    }
+    fn swap_shaders(&mut self, shaders: Shaders<R>) -> Shaders<R> {
+        mem::replace(&mut self.shaders, shaders)
+    }
+}
+
+/// Draws textured objects without lighting, e.g. video screens and UI panels
+pub struct TexturedStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for TexturedStyle<R> {
+    type Vertex = VertNT;
+    type Inputs = TexturedInputs<R>;
+    type Material = TexturedMaterial<R>;
+
+    fn material_key(mat: &TexturedMaterial<R>) -> u64 {
+        MaterialKey::new()
+            .texture(&mat.albedo)
+            .factors(&mat.tint)
+            .finish()
+    }
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut TexturedInputs<R>,
+        p: Primitive,
+        o: &RasterOptions,
+        _: Features,
+        mode: ViewMode,
+    ) -> Result<Self, Error> {
+        let stereo;
+        let shaders = match mode {
+            ViewMode::Single => &i.shaders,
+            ViewMode::Stereo => {
+                stereo = stereo_shader(f, p)?;
+                &stereo
+            },
+        };
+        Ok(TexturedStyle {
+            pso: shaders.pipeline_state(f, p, o.rasterizer(), pipeline_init!(pl, o))?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<TexturedInputs<R>, Error> {
+        Ok(TexturedInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: BlockBuffer::new(f),
+            material_block: BlockBuffer::new(f),
+            defaults: DefaultTextures::new(f)?,
+        })
+    }
+
+    fn draw_raw<C>(
+        &self,
+        inputs: &mut TexturedInputs<R>,
+        enc: &mut Encoder<R, C>,
+        target: &EyeTarget<R>,
+        slice: &Slice<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &TexturedMaterial<R>,
+    )
+        -> Result<usize, Error>
+        where C: CommandBuffer<R>
+    {
+        let mut updates = 0;
+        if let Some(t) = inputs.transform.take() {
+            updates += inputs.transform_block.update(enc, &t);
+        }
+        updates += inputs.material_block.update(enc, &TexturedBlock {
+            tint: mat.tint,
+        });
+        enc.draw(slice, &self.pso, &pl::Data {
+            color: target.color.clone(),
+            depth: target.depth.clone(),
+            verts: buf,
+            transform: inputs.transform_block.buffer().clone(),
+            material: inputs.material_block.buffer().clone(),
+            albedo: or_default(&mat.albedo, &inputs.defaults.white_srgb),
+        });
+        Ok(updates)
+    }
+}
@@ -1,50 +1,96 @@
-use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Slice, Encoder};
 use gfx::pso::PipelineState;
 use gfx::traits::FactoryExt;
 use gfx::handle::{Buffer, DepthStencilView};
-use gfx::state::Rasterizer;
 use gfx::format::*;
 
-use nalgebra::{self as na, Rotation3, Vector3, Matrix4};
+use nalgebra::{self as na, Rotation3, Vector3};
 
-use super::{StyleInputs, Style, TransformBlock};
+use super::{StyleInputs, Style, RasterOptions, Features, ViewMode, PipelineKey, TransformBlock, DefaultTextures, or_default, fill_rasterizer, TargetFormats, RawDepthTarget, EyeTarget, ToneMap, color_target, depth_target, BlockBuffer, MaterialKey};
+use super::shaders::Shaders;
 use ::mesh::{Primitive, MeshSource, Mesh, Indexing, Vert, VertNTT};
-use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+use ::{Error, Texture};
 use ::util::NativeRepr;
-use std::mem::transmute;
+use std::mem::{self, transmute};
 
 pub type LumMapFormat = (R32_G32_B32, Float);
 
-/// The collection of mesh textures used by physically based rendering
+/// The collection of mesh textures and factors used by physically based rendering.
+/// Each map is optional and multiplied by its corresponding factor, so a plain-colored
+/// object can be described by factors alone. Missing maps are replaced by white
+/// (or a flat normal map).
 #[derive(Clone)]
 pub struct UberMaterial<R: Resources> {
     /// normal map
-    pub normal: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    pub normal: Option<Texture<R, (R8_G8_B8_A8, Unorm)>>,
     /// albedo map (base color)
-    pub albedo: Texture<R, (R8_G8_B8_A8, Srgb)>,
-    /// metalness (1=metal, 0=dielectric), roughness, flatness (0=PBR, 1=flat color) map
-    pub knobs: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    pub albedo: Option<Texture<R, (R8_G8_B8_A8, Srgb)>>,
+    /// metalness (1=metal, 0=dielectric), roughness, flatness (0=PBR, 1=flat color), ambient occlusion map
+    pub knobs: Option<Texture<R, (R8_G8_B8_A8, Unorm)>>,
+    /// emissive map (light given off by the surface)
+    pub emissive: Option<Texture<R, (R8_G8_B8_A8, Srgb)>>,
+    /// linear color (and alpha) multiplied with the albedo map
+    pub albedo_factor: [f32; 4],
+    /// multiplied with the metalness channel of the knobs map
+    pub metalness_factor: f32,
+    /// multiplied with the roughness channel of the knobs map
+    pub roughness_factor: f32,
+    /// multiplied with the flatness channel of the knobs map (0 by default, so that untextured materials are lit)
+    pub flatness_factor: f32,
+    /// linear color multiplied with the emissive map (black by default, so that materials
+    /// only give off light once it is set)
+    pub emissive_factor: [f32; 3],
+    /// multiplier applied to the emissive color before it is added to the HDR luminance,
+    /// for emission brighter than 1 (1 by default)
+    pub emissive_intensity: f32,
+    /// strength of the normal map (0=flat, 1=unchanged)
+    pub normal_factor: f32,
+}
+
+impl<R: Resources> Default for UberMaterial<R> {
+    fn default() -> UberMaterial<R> {
+        UberMaterial {
+            normal: None,
+            albedo: None,
+            knobs: None,
+            emissive: None,
+            albedo_factor: [1.; 4],
+            metalness_factor: 1.,
+            roughness_factor: 1.,
+            flatness_factor: 0.,
+            emissive_factor: [0.; 3],
+            emissive_intensity: 1.,
+            normal_factor: 1.,
+        }
+    }
 }
 
 gfx_defines!{
+    constant MaterialBlock {
+        albedo: [f32; 4] = "albedo_factor",
+        emissive: [f32; 4] = "emissive_factor",
+        metalness: f32 = "metalness_factor",
+        roughness: f32 = "roughness_factor",
+        flatness: f32 = "flatness_factor",
+        normal: f32 = "normal_factor",
+    }
+
     constant ParamsBlock {
         sun_matrix: [[f32; 4]; 4] = "sun_matrix",
         sun_color: [f32; 4] = "sun_color",
         sun_in_env: f32 = "sun_in_env",
         radiance_levels: i32 = "radiance_levels",
-
-        gamma: f32 = "gamma",
         exposure: f32 = "exposure",
+        tone_map: i32 = "tone_map",
     }
 
     pipeline bg {
         verts: gfx::VertexBuffer<Vert> = (),
         transform: gfx::ConstantBuffer<TransformBlock> = "transform",
         params: gfx::ConstantBuffer<ParamsBlock> = "params",
-        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
 
-        color: gfx::RenderTarget<ColorFormat> = "f_color",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::PASS_TEST,
+        color: gfx::RawRenderTarget = color_target("f_color", None),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::PASS_TEST),
 
         radiance: gfx::TextureSampler<[f32; 3]> = "cube_map",
     }
@@ -53,14 +99,15 @@ gfx_defines!{
         verts: gfx::VertexBuffer<VertNTT> = (),
         transform: gfx::ConstantBuffer<TransformBlock> = "transform",
         params: gfx::ConstantBuffer<ParamsBlock> = "params",
-        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
+        material: gfx::ConstantBuffer<MaterialBlock> = "material",
 
-        color: gfx::RenderTarget<ColorFormat> = "f_color",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::LESS_EQUAL_WRITE,
+        color: gfx::RawRenderTarget = color_target("f_color", None),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::LESS_EQUAL_WRITE),
 
         normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
         albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
         knobs: gfx::TextureSampler<[f32; 4]> = "knobs_tex",
+        emissive: gfx::TextureSampler<[f32; 4]> = "emissive_tex",
         irradiance: gfx::TextureSampler<[f32; 3]> = "irradiance_map",
         radiance: gfx::TextureSampler<[f32; 3]> = "radiance_map",
         integrated_brdf: gfx::TextureSampler<[f32; 2]> = "integrated_brdf_map",
@@ -69,7 +116,12 @@ gfx_defines!{
     }
 }
 
-shader!(shader {
+/// The optional features of the uber shader
+const FEATURES: &[&str] = &["NORMAL_MAP", "EMISSIVE"];
+const NORMAL_MAP: Features = Features(1 << 0);
+const EMISSIVE: Features = Features(1 << 1);
+
+shader!(shader, stereo_shader, features {
     vertex: static_file!("shaders/transform.v.glsl")
         .define("NORM")
         .define("TEX")
@@ -82,7 +134,7 @@ shader!(shader {
         .define_to("I_BITAN", "v_bitan")
 });
 
-shader!(bg_shader {
+shader!(bg_shader, bg_stereo_shader {
     vertex: static_file!("shaders/transform.v.glsl")
         .define_to("W_COORD", 1.),
     fragment: static_file!("shaders/cubebg.f.glsl")
@@ -101,23 +153,31 @@ pub struct UberEnv<R: Resources> {
 
 /// The configuration for physically based rendering
 pub struct UberInputs<R: Resources> {
-    shaders: ShaderSet<R>,
+    shaders: Shaders<R>,
     background: UberBackground<R>,
     transform: Option<TransformBlock>,
-    transform_block: Buffer<R, TransformBlock>,
+    transform_block: BlockBuffer<R, TransformBlock>,
     env: UberEnv<R>,
     exposure: f32,
-    gamma: f32,
+    tone_map: ToneMap,
     params_update: bool,
     params_block: Buffer<R, ParamsBlock>,
+    material_block: BlockBuffer<R, MaterialBlock>,
+    defaults: DefaultTextures<R>,
     integrated_brdf: Texture<R, (R8_G8, Unorm)>,
     shadow_depth: Texture<R, (D32, Float)>,
 }
 
 struct UberBackground<R: Resources> {
+    /// The owner of the pipelines, for `FrameStats`
+    id: usize,
     pso: PipelineState<R, bg::Meta>,
+    /// Built along with the first stereo variant of the meshes' pipelines
+    stereo_pso: Option<PipelineState<R, bg::Meta>>,
     // shaders: ShaderSet<R>,
     mesh: Mesh<R, Vert, ()>,
+    /// The target formats of the pipelines, which follow those of the meshes' pipelines
+    formats: TargetFormats,
 }
 
 impl<R: Resources> UberInputs<R> {
@@ -131,22 +191,47 @@ impl<R: Resources> UberInputs<R> {
         &mut self.env
     }
 
+    /// Set the exposure that the `PostChain` applies, which flat colors (see
+    /// `UberMaterial::knobs`) are blended in after. Keep it in sync with
+    /// `PostChain::current_exposure` when automatic exposure is enabled.
     pub fn set_exposure(&mut self, exposure: f32) {
         self.exposure = exposure;
         self.params_update = true;
     }
 
-    pub fn set_gamma(&mut self, gamma: f32) {
-        self.gamma = gamma;
+    /// Set the tone mapping operator that the `PostChain` applies, like `set_exposure`.
+    pub fn set_tone_map(&mut self, tone_map: ToneMap) {
+        self.tone_map = tone_map;
         self.params_update = true;
     }
+
+    /// Gamma correction is applied by the `PostChain` (see `PostChain::gamma`), so this
+    /// does nothing.
+    #[deprecated(note = "gamma correction is applied by `PostChain::gamma`")]
+    pub fn set_gamma(&mut self, _gamma: f32) {}
 }
 
 impl<R: Resources> StyleInputs<R> for UberInputs<R> {
+    /// The shaders of meshes, and the single view and stereo pipeline states of the background
+    type Shaders = (Shaders<R>, PipelineState<R, bg::Meta>, Option<PipelineState<R, bg::Meta>>);
     fn transform(&mut self, block: TransformBlock) {
         self.transform = Some(block);
     }
-    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+    fn shader_set(&self) -> &ShaderSet<R> { self.shaders.set() }
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Self::Shaders, Error> {
+        let stereo_pso = match self.background.stereo_pso {
+            Some(_) => Some(background_pipeline(f, self.background.formats, ViewMode::Stereo)?),
+            None => None,
+        };
+        Ok((shader(f, &[])?, background_pipeline(f, self.background.formats, ViewMode::Single)?, stereo_pso))
+    }
+    fn swap_shaders(&mut self, (shaders, pso, stereo_pso): Self::Shaders) -> Self::Shaders {
+        (
+            mem::replace(&mut self.shaders, shaders),
+            mem::replace(&mut self.background.pso, pso),
+            mem::replace(&mut self.background.stereo_pso, stereo_pso),
+        )
+    }
 }
 
 /// Draws meshes using a physically based rendering pipeline
@@ -154,24 +239,25 @@ pub struct UberStyle<R: Resources> {
     pso: PipelineState<R, pl::Meta>,
 }
 
-fn shadow_texture<R: Resources, F: Factory<R>>(factory: &mut F)
-    -> (DepthStencilView<R, (D32, Float)>, Texture<R, (D32, Float)>)
+/// A depth buffer of the given size that can be sampled, for shadow maps.
+pub fn shadow_texture<R: Resources, F: Factory<R>>(factory: &mut F, width: u16, height: u16)
+    -> Result<(DepthStencilView<R, (D32, Float)>, Texture<R, (D32, Float)>), Error>
 {
     use gfx::texture::*;
     use gfx::memory::{Bind, Usage};
     
     let shadow_tex = {
-        let kind = Kind::D2(512, 512, AaMode::Single);
+        let kind = Kind::D2(width, height, AaMode::Single);
         let bind = Bind::SHADER_RESOURCE | Bind::DEPTH_STENCIL;
         let ctype = Some(gfx::format::ChannelType::Float);
 
-        factory.create_texture(kind, 1, bind, Usage::Data, ctype).unwrap()
+        factory.create_texture(kind, 1, bind, Usage::Data, ctype)?
     };
 
     let resource = factory.view_texture_as_shader_resource
         ::<::ShadowDepthFormat>(
             &shadow_tex, (0, 0), gfx::format::Swizzle::new()
-        ).unwrap();
+        )?;
 
     let mut sampler_info = SamplerInfo::new(
         FilterMethod::Bilinear,
@@ -182,12 +268,32 @@ fn shadow_texture<R: Resources, F: Factory<R>>(factory: &mut F)
 
     let shadow_depth_target = factory.view_texture_as_depth_stencil(
         &shadow_tex, 0, None,
-        DepthStencilFlags::empty()).unwrap();
+        DepthStencilFlags::empty())?;
 
-    (shadow_depth_target, Texture {
+    Ok((shadow_depth_target, Texture {
         buffer: resource,
         sampler: sampler,
-    })
+    }))
+}
+
+/// The pipeline state of the environment background for the given view mode, drawing
+/// into targets of the given formats.
+fn background_pipeline<R: Resources, F: Factory<R>>(f: &mut F, formats: TargetFormats, mode: ViewMode)
+    -> Result<PipelineState<R, bg::Meta>, Error>
+{
+    let o = RasterOptions {
+        formats: formats,
+        .. Default::default()
+    };
+    let shaders = match mode {
+        ViewMode::Single => bg_shader(f)?,
+        ViewMode::Stereo => bg_stereo_shader(f, Primitive::TriangleList)?,
+    };
+    shaders.pipeline_state(
+        f,
+        Primitive::TriangleList,
+        fill_rasterizer(),
+        pipeline_init!(bg, &o))
 }
 
 impl<R: Resources> Style<R> for UberStyle<R> {
@@ -195,14 +301,63 @@ impl<R: Resources> Style<R> for UberStyle<R> {
     type Inputs = UberInputs<R>;
     type Material = UberMaterial<R>;
 
+    fn features() -> &'static [&'static str] { FEATURES }
+
+    fn material_features(mat: &UberMaterial<R>) -> Features {
+        let mut features = Features::none();
+        if mat.normal.is_some() && mat.normal_factor != 0. {
+            features = features | NORMAL_MAP;
+        }
+        if mat.emissive_intensity != 0. && mat.emissive_factor != [0.; 3] {
+            features = features | EMISSIVE;
+        }
+        features
+    }
+
+    fn material_key(mat: &UberMaterial<R>) -> u64 {
+        let e = mat.emissive_factor;
+        MaterialKey::new()
+            .texture(&mat.normal)
+            .texture(&mat.albedo)
+            .texture(&mat.knobs)
+            .texture(&mat.emissive)
+            .factors(&mat.albedo_factor)
+            .factors(&[e[0], e[1], e[2], mat.emissive_intensity])
+            .factors(&[mat.metalness_factor, mat.roughness_factor, mat.flatness_factor, mat.normal_factor])
+            .finish()
+    }
+
     fn new<F: Factory<R> + FactoryExt<R>> (
         f: &mut F,
         i: &mut UberInputs<R>,
         p: Primitive,
-        r: Rasterizer,
+        o: &RasterOptions,
+        features: Features,
+        mode: ViewMode,
     ) -> Result<Self, Error> {
+        if i.background.formats != o.formats {
+            i.background.pso = background_pipeline(f, o.formats, ViewMode::Single)?;
+            i.background.stereo_pso = None;
+            i.background.formats = o.formats;
+        }
+        if mode == ViewMode::Stereo && i.background.stereo_pso.is_none() {
+            i.background.stereo_pso = Some(background_pipeline(f, o.formats, ViewMode::Stereo)?);
+        }
+        let defines = features.defines(FEATURES);
+        let variant;
+        let shaders = match mode {
+            ViewMode::Single if features == Features::none() => &i.shaders,
+            ViewMode::Single => {
+                variant = shader(f, &defines)?;
+                &variant
+            },
+            ViewMode::Stereo => {
+                variant = stereo_shader(f, p, &defines)?;
+                &variant
+            },
+        };
         Ok(UberStyle {
-            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+            pso: shaders.pipeline_state(f, p, o.rasterizer(), pipeline_init!(pl, o))?,
         })
     }
 
@@ -213,8 +368,8 @@ impl<R: Resources> Style<R> for UberStyle<R> {
         let bg_bytes = unsafe {
             transmute::<[f32; 3], [u32; 3]>(bg_color)
         };
-        let (_, shadow_depth) = shadow_texture(f);
-        let bg_shaders = bg_shader(f)?;
+        let (_, shadow_depth) = shadow_texture(f, 512, 512)?;
+        let bg_pso = background_pipeline(f, TargetFormats::default(), ViewMode::Single)?;
         let bg_verts = vec![
             Vert { pos: [-10., -10.,  10.] },
             Vert { pos: [-10.,  10.,  10.] },
@@ -240,13 +395,11 @@ impl<R: Resources> Style<R> for UberStyle<R> {
             6-1, 2-1, 4-1,
         ];
         Ok(UberInputs {
-            shaders: shader(f)?,
+            shaders: shader(f, &[])?,
             background: UberBackground {
-                pso: f.create_pipeline_state(
-                    &bg_shaders,
-                    Primitive::TriangleList,
-                    Rasterizer::new_fill(),
-                    bg::new())?,
+                id: PipelineKey::new_owner(),
+                pso: bg_pso,
+                stereo_pso: None,
                 // shaders: bg_shaders,
                 mesh: MeshSource {
                     verts: bg_verts,
@@ -254,13 +407,16 @@ impl<R: Resources> Style<R> for UberStyle<R> {
                     mat: (),
                     prim: Primitive::TriangleList,
                 }.upload(f),
+                formats: TargetFormats::default(),
             },
             transform: None,
-            transform_block: f.create_constant_buffer(1),
+            transform_block: BlockBuffer::new(f),
+            exposure: 1.,
+            tone_map: ToneMap::Exponential,
             params_update: true,
             params_block: f.create_constant_buffer(1),
-            gamma: 2.2,
-            exposure: 1.0,
+            material_block: BlockBuffer::new(f),
+            defaults: DefaultTextures::new(f)?,
             integrated_brdf: ::load::load_integrated_brdf(f)?,
             env: UberEnv {
                 radiance: Texture::uniform_value(f, bg_bytes)?,
@@ -281,18 +437,17 @@ impl<R: Resources> Style<R> for UberStyle<R> {
         &self,
         inputs: &mut UberInputs<R>,
         enc: &mut Encoder<R, C>,
-        color: TargetRef<R>,
-        depth: DepthRef<R>,
-        scissor: Rect,
+        target: &EyeTarget<R>,
         slice: &Slice<R>,
         buf: Buffer<R, Self::Vertex>,
         mat: &UberMaterial<R>,
     )
-        -> Result<(), Error>
+        -> Result<usize, Error>
         where C: CommandBuffer<R>
     {
+        let mut updates = 0;
         if let Some(t) = inputs.transform.take() {
-            enc.update_constant_buffer(&inputs.transform_block, &t);
+            updates += inputs.transform_block.update(enc, &t);
         }
         if inputs.params_update {
             let mat: Rotation3<f32> = na::convert(inputs.env.sun_rotation);
@@ -300,27 +455,38 @@ impl<R: Resources> Style<R> for UberStyle<R> {
                 sun_matrix: mat.to_homogeneous().downgrade(),
                 sun_color: inputs.env.sun_color,
                 sun_in_env: if inputs.env.sun_included { 1. } else { 0. },
-                exposure: inputs.exposure,
-                gamma: inputs.gamma,
                 radiance_levels: inputs.env.radiance_levels as i32,
+                exposure: inputs.exposure,
+                tone_map: inputs.tone_map.index(),
             });
+            updates += 1;
         }
+        let e = mat.emissive_factor;
+        updates += inputs.material_block.update(enc, &MaterialBlock {
+            albedo: mat.albedo_factor,
+            emissive: [e[0], e[1], e[2], mat.emissive_intensity],
+            metalness: mat.metalness_factor,
+            roughness: mat.roughness_factor,
+            flatness: mat.flatness_factor,
+            normal: mat.normal_factor,
+        });
         enc.draw(slice, &self.pso, &pl::Data {
-            color: color,
-            depth: depth,
+            color: target.color.clone(),
+            depth: target.depth.clone(),
             verts: buf,
-            scissor: scissor,
-            transform: inputs.transform_block.clone(),
+            transform: inputs.transform_block.buffer().clone(),
             params: inputs.params_block.clone(),
-            normal: mat.normal.clone().into_tuple(),
-            albedo: mat.albedo.clone().into_tuple(),
-            knobs: mat.knobs.clone().into_tuple(),
+            material: inputs.material_block.buffer().clone(),
+            normal: or_default(&mat.normal, &inputs.defaults.normal),
+            albedo: or_default(&mat.albedo, &inputs.defaults.white_srgb),
+            knobs: or_default(&mat.knobs, &inputs.defaults.white),
+            emissive: or_default(&mat.emissive, &inputs.defaults.white_srgb),
             integrated_brdf: inputs.integrated_brdf.clone().into_tuple(),
             irradiance: inputs.env.irradiance.clone().into_tuple(),
             radiance: inputs.env.radiance.clone().into_tuple(),
             shadow_depth: inputs.shadow_depth.clone().into_tuple(),
         });
-        Ok(())
+        Ok(updates)
     }
 }
 
@@ -329,35 +495,51 @@ impl<R: Resources> super::Painter<R, UberStyle<R>> {
         &self,
         ctx: &mut super::DrawParams<R, C>,
     ) {
-        let inputs = self.inputs.borrow();
+        let mut inputs = self.inputs.borrow_mut();
+        let inputs = &mut *inputs;
         let bgin = &inputs.background;
         let mat: Rotation3<f32> = na::convert(inputs.env.sun_rotation);
         ctx.encoder.update_constant_buffer(&inputs.params_block, &ParamsBlock { 
             sun_matrix: mat.to_homogeneous().downgrade(),
             sun_color: inputs.env.sun_color,
             sun_in_env: if inputs.env.sun_included { 1. } else { 0. },
-            exposure: inputs.exposure,
-            gamma: inputs.gamma,
             radiance_levels: inputs.env.radiance_levels as i32,
+            exposure: inputs.exposure,
+            tone_map: inputs.tone_map.index(),
         });
-        for eye in &[&ctx.left, &ctx.right] {
-            let trans = TransformBlock {
-                eye: eye.eye.to_homogeneous().downgrade(),
-                model: Matrix4::identity().downgrade(),
-                view: eye.view.downgrade(),
-                proj: eye.proj.downgrade(),
-                clip_offset: eye.clip_offset,
-            };
-            ctx.encoder.update_constant_buffer(&inputs.transform_block, &trans);
-            ctx.encoder.draw(&bgin.mesh.slice, &bgin.pso, &bg::Data {
-                color: ctx.color.clone(),
-                depth: ctx.depth.clone(),
+        let mut passes = Vec::new();
+        // until a stereo variant has been built, the stereo views are drawn separately
+        let first = match (ctx.stereo.as_ref(), bgin.stereo_pso.as_ref()) {
+            (Some(target), Some(pso)) if ctx.views.len() >= 2 => {
+                let trans = TransformBlock::stereo(na::one(), &ctx.views[0].eye, &ctx.views[1].eye);
+                passes.push((trans, target.clone(), pso, ViewMode::Stereo));
+                2
+            },
+            _ => 0,
+        };
+        for v in &ctx.views[first..] {
+            passes.push((TransformBlock::single(na::one(), &v.eye), v.target.clone(), &bgin.pso, ViewMode::Single));
+        }
+        // The parameters were updated before the first draw
+        let mut updates = 1;
+        for (trans, target, pso, mode) in passes {
+            updates += inputs.transform_block.update(&mut ctx.encoder, &trans);
+            ctx.encoder.draw(&bgin.mesh.slice, pso, &bg::Data {
+                color: target.color.clone(),
+                depth: target.depth.clone(),
                 verts: bgin.mesh.buf.clone(),
-                scissor: eye.clip,
-                transform: inputs.transform_block.clone(),
+                transform: inputs.transform_block.buffer().clone(),
                 params: inputs.params_block.clone(),
                 radiance: inputs.env.radiance.clone().into_tuple(),
             });
+            let key = PipelineKey {
+                owner: bgin.id,
+                prim: bgin.mesh.prim,
+                features: Features::none(),
+                mode: mode,
+            };
+            ctx.stats.draw(key, bgin.mesh.prim, &bgin.mesh.slice, updates);
+            updates = 0;
         }
     }
 }
@@ -1,12 +1,13 @@
-use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Slice, Encoder};
 use gfx::pso::PipelineState;
 use gfx::traits::FactoryExt;
 use gfx::handle::Buffer;
-use gfx::state::Rasterizer;
+use std::mem;
 
-use super::{StyleInputs, Style, TransformBlock};
+use super::{StyleInputs, Style, RasterOptions, Features, ViewMode, TransformBlock, RawDepthTarget, EyeTarget, color_target, depth_target, BlockBuffer};
+use super::shaders::Shaders;
 use ::mesh::{Primitive, VertN};
-use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef};
+use ::Error;
 
 gfx_defines!{
     constant UnishadeBlock {
@@ -18,13 +19,12 @@ gfx_defines!{
         verts: gfx::VertexBuffer<VertN> = (),
         transform: gfx::ConstantBuffer<TransformBlock> = "transform",
         shade: gfx::ConstantBuffer<UnishadeBlock> = "shade",
-        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
-        color: gfx::RenderTarget<ColorFormat> = "f_color",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::LESS_EQUAL_WRITE,
+        color: gfx::RawRenderTarget = color_target("f_color", None),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::LESS_EQUAL_WRITE),
     }
 }
 
-shader!(shader {
+shader!(shader, stereo_shader {
     vertex: static_file!("shaders/transform.v.glsl")
         .define("NORM"),
     fragment: static_file!("shaders/unishade.f.glsl")
@@ -34,9 +34,9 @@ shader!(shader {
 
 /// The configuration for unishade rendering
 pub struct UnishadeInputs<R: Resources> {
-    shaders: ShaderSet<R>,
+    shaders: Shaders<R>,
     transform: Option<TransformBlock>,
-    transform_block: Buffer<R, TransformBlock>,
+    transform_block: BlockBuffer<R, TransformBlock>,
     shade: Option<UnishadeBlock>,
     shade_block: Buffer<R, UnishadeBlock>,
 }
@@ -52,8 +52,15 @@ impl<R: Resources> UnishadeInputs<R> {
 }
 
 impl<R: Resources> StyleInputs<R> for UnishadeInputs<R> {
+    type Shaders = Shaders<R>;
     fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
-    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+    fn shader_set(&self) -> &ShaderSet<R> { self.shaders.set() }
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Shaders<R>, Error> {
+        shader(f)
+    }
+    fn swap_shaders(&mut self, shaders: Shaders<R>) -> Shaders<R> {
+        mem::replace(&mut self.shaders, shaders)
+    }
 }
 
 /// Draws objects with very simple lighting: one color on the top and a different color on the bottom
@@ -70,10 +77,20 @@ impl<R: Resources> Style<R> for UnishadeStyle<R> {
         f: &mut F,
         i: &mut UnishadeInputs<R>,
         p: Primitive,
-        r: Rasterizer,
+        o: &RasterOptions,
+        _: Features,
+        mode: ViewMode,
     ) -> Result<Self, Error> {
+        let stereo;
+        let shaders = match mode {
+            ViewMode::Single => &i.shaders,
+            ViewMode::Stereo => {
+                stereo = stereo_shader(f, p)?;
+                &stereo
+            },
+        };
         Ok(UnishadeStyle {
-            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+            pso: shaders.pipeline_state(f, p, o.rasterizer(), pipeline_init!(pl, o))?,
         })
     }
 
@@ -83,7 +100,7 @@ impl<R: Resources> Style<R> for UnishadeStyle<R> {
         Ok(UnishadeInputs {
             shaders: shader(f)?,
             transform: None,
-            transform_block: f.create_constant_buffer(1),
+            transform_block: BlockBuffer::new(f),
             shade: None,
             shade_block: f.create_constant_buffer(1),
         })
@@ -93,30 +110,29 @@ impl<R: Resources> Style<R> for UnishadeStyle<R> {
         &self,
         inputs: &mut UnishadeInputs<R>,
         enc: &mut Encoder<R, C>,
-        color: TargetRef<R>,
-        depth: DepthRef<R>,
-        scissor: Rect,
+        target: &EyeTarget<R>,
         slice: &Slice<R>,
         buf: Buffer<R, Self::Vertex>,
         _: &(),
     )
-        -> Result<(), Error>
+        -> Result<usize, Error>
         where C: CommandBuffer<R>
     {
+        let mut updates = 0;
         if let Some(t) = inputs.transform.take() {
-            enc.update_constant_buffer(&inputs.transform_block, &t);
+            updates += inputs.transform_block.update(enc, &t);
         }
         if let Some(shade) = inputs.shade.take() {
             enc.update_constant_buffer(&inputs.shade_block, &shade);
+            updates += 1;
         }
         enc.draw(slice, &self.pso, &pl::Data {
-            color: color,
-            depth: depth,
+            color: target.color.clone(),
+            depth: target.depth.clone(),
             verts: buf,
-            scissor: scissor,
-            transform: inputs.transform_block.clone(),
+            transform: inputs.transform_block.buffer().clone(),
             shade: inputs.shade_block.clone(),
         });
-        Ok(())
+        Ok(updates)
     }
 }
\ No newline at end of file
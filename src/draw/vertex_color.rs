@@ -0,0 +1,160 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use std::mem;
+use nalgebra::Vector3;
+
+use super::{StyleInputs, Style, RasterOptions, Features, ViewMode, TransformBlock, RawDepthTarget, EyeTarget, color_target, depth_target, BlockBuffer};
+use super::shaders::Shaders;
+use ::mesh::{Primitive, VertNC};
+use ::Error;
+
+gfx_defines!{
+    constant LightingBlock {
+        sky: [f32; 4] = "sky",
+        ground: [f32; 4] = "ground",
+        sun_dir: [f32; 4] = "sun_dir",
+        sun_color: [f32; 4] = "sun_color",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNC> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        lighting: gfx::ConstantBuffer<LightingBlock> = "lighting",
+        color: gfx::RawRenderTarget = color_target("f_lum", None),
+        depth: RawDepthTarget = depth_target(gfx::preset::depth::LESS_EQUAL_WRITE),
+    }
+}
+
+shader!(shader, stereo_shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("NORM")
+        .define("COLOR"),
+    fragment: static_file!("shaders/vertex_color.f.glsl")
+        .define_to("I_POS", "v_pos")
+        .define_to("I_NORM", "v_norm")
+        .define_to("I_COLOR", "v_color")
+});
+
+/// The configuration for vertex color rendering. Colors are in linear space, with
+/// the alpha channel as a multiplier (intensity).
+pub struct VertexColorInputs<R: Resources> {
+    shaders: Shaders<R>,
+    transform: Option<TransformBlock>,
+    transform_block: BlockBuffer<R, TransformBlock>,
+    lighting: LightingBlock,
+    lighting_changed: bool,
+    lighting_block: Buffer<R, LightingBlock>,
+}
+
+impl<R: Resources> VertexColorInputs<R> {
+    /// Sets the ambient light, which fades from the ground color on downward facing
+    /// surfaces to the sky color on upward facing surfaces
+    pub fn hemisphere(&mut self, sky: [f32; 4], ground: [f32; 4]) {
+        self.lighting.sky = sky;
+        self.lighting.ground = ground;
+        self.lighting_changed = true;
+    }
+
+    /// Sets the directional light, shining in the given direction
+    pub fn sun(&mut self, dir: Vector3<f32>, color: [f32; 4]) {
+        self.lighting.sun_dir = [dir.x, dir.y, dir.z, 0.];
+        self.lighting.sun_color = color;
+        self.lighting_changed = true;
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for VertexColorInputs<R> {
+    type Shaders = Shaders<R>;
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { self.shaders.set() }
+    fn build_shaders<F: Factory<R>>(&self, f: &mut F) -> Result<Shaders<R>, Error> {
+        shader(f)
+    }
+    fn swap_shaders(&mut self, shaders: Shaders<R>) -> Shaders<R> {
+        mem::replace(&mut self.shaders, shaders)
+    }
+}
+
+/// Draws objects using the per-vertex color attribute, lit by hemisphere
+/// ambient light and a directional light
+pub struct VertexColorStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for VertexColorStyle<R> {
+    type Vertex = VertNC;
+    type Inputs = VertexColorInputs<R>;
+    type Material = ();
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut VertexColorInputs<R>,
+        p: Primitive,
+        o: &RasterOptions,
+        _: Features,
+        mode: ViewMode,
+    ) -> Result<Self, Error> {
+        let stereo;
+        let shaders = match mode {
+            ViewMode::Single => &i.shaders,
+            ViewMode::Stereo => {
+                stereo = stereo_shader(f, p)?;
+                &stereo
+            },
+        };
+        Ok(VertexColorStyle {
+            pso: shaders.pipeline_state(f, p, o.rasterizer(), pipeline_init!(pl, o))?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<VertexColorInputs<R>, Error> {
+        Ok(VertexColorInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: BlockBuffer::new(f),
+            lighting: LightingBlock {
+                sky: [1., 1., 1., 0.4],
+                ground: [1., 1., 1., 0.1],
+                sun_dir: [-0.3, -1., -0.2, 0.],
+                sun_color: [1., 1., 1., 1.],
+            },
+            lighting_changed: true,
+            lighting_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn draw_raw<C>(
+        &self,
+        inputs: &mut VertexColorInputs<R>,
+        enc: &mut Encoder<R, C>,
+        target: &EyeTarget<R>,
+        slice: &Slice<R>,
+        buf: Buffer<R, Self::Vertex>,
+        _: &(),
+    )
+        -> Result<usize, Error>
+        where C: CommandBuffer<R>
+    {
+        let mut updates = 0;
+        if let Some(t) = inputs.transform.take() {
+            updates += inputs.transform_block.update(enc, &t);
+        }
+        if inputs.lighting_changed {
+            enc.update_constant_buffer(&inputs.lighting_block, &inputs.lighting);
+            updates += 1;
+            inputs.lighting_changed = false;
+        }
+        enc.draw(slice, &self.pso, &pl::Data {
+            color: target.color.clone(),
+            depth: target.depth.clone(),
+            verts: buf,
+            transform: inputs.transform_block.buffer().clone(),
+            lighting: inputs.lighting_block.clone(),
+        });
+        Ok(updates)
+    }
+}
@@ -10,4 +10,15 @@ pub enum FlightError {
     CubemapSizeMismatch {
         expected: u32,
     },
+    #[fail(display = "Could not compile shader {} (defines: {})\n{}", name, defines, details)]
+    ShaderCompile {
+        name: String,
+        defines: String,
+        details: String,
+    },
+    #[fail(display = "Could not link shaders {}\n{}", shaders, log)]
+    ShaderLink {
+        shaders: String,
+        log: String,
+    },
 }
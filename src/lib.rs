@@ -3,6 +3,7 @@
 extern crate log;
 #[macro_use]
 extern crate gfx;
+extern crate gfx_core;
 extern crate nalgebra;
 extern crate obj as wavefront;
 extern crate fnv;
@@ -24,6 +25,8 @@ pub mod load;
 pub mod mesh;
 /// VR hardware interface
 pub mod vr;
+/// Reloading of changed shaders and assets
+pub mod watch;
 
 mod error;
 pub use error::FlightError;
@@ -37,14 +40,18 @@ use gfx::format::*;
 use nalgebra::{Point3, UnitQuaternion, Point2};
 pub use failure::Error;
 
-/// The pixel format of color drawing targets
+/// The default pixel format of display targets (see `draw::PostChain::with_output`)
 pub type ColorFormat = (R8_G8_B8_A8, Unorm);
-/// The pixel format of depth drawing targets
+/// The default pixel format of HDR (floating-point) color drawing targets (see `draw::TargetFormats`)
+pub type HdrColorFormat = (R16_G16_B16_A16, Float);
+/// The default pixel format of depth drawing targets (see `draw::TargetFormats`)
 pub type DepthFormat = (D24_S8, Unorm);
 /// The pixel format of shadow depth buffers
 pub type ShadowDepthFormat = (D32, Float);
 /// Reference to a GPU color target
 pub type TargetRef<R> = RenderTargetView<R, ColorFormat>;
+/// Reference to a GPU HDR color target
+pub type HdrTargetRef<R> = RenderTargetView<R, HdrColorFormat>;
 /// Reference to a GPU depth target
 pub type DepthRef<R> = DepthStencilView<R, DepthFormat>;
 /// The result of compiling and linking shader programs
@@ -104,6 +111,7 @@ impl<R: gfx::Resources, T: TextureFormat> Texture<R, T> {
     }
 
     /// Build a single-pixel (single value) texture. The overhead on this might still be fairly high, even though the memory usage is minimal.
+    /// For plain-colored meshes, prefer the factors on `UberMaterial` or `PbrMaterial`.
     pub fn uniform_value<F>(f: &mut F, val: <<T as Formatted>::Surface as SurfaceTyped>::DataType)
         -> Result<Self, Error>
         where F: gfx::Factory<R>
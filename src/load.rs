@@ -133,9 +133,11 @@ pub fn open_uber_mesh<R, F, P1, P2, P3, P4>(
     Ok(open_wavefront(wavefront)?
     .compute_tan()
     .with_material(draw::UberMaterial {
-        albedo: open_rgba8(f, albedo, sampler.clone())?,
-        normal: open_rgba8(f, normal, sampler.clone())?,
-        knobs: open_rgba8(f, knobs, sampler)?,
+        albedo: Some(open_rgba8(f, albedo, sampler.clone())?),
+        normal: Some(open_rgba8(f, normal, sampler.clone())?),
+        knobs: Some(open_rgba8(f, knobs, sampler)?),
+        flatness_factor: 1.,
+        .. Default::default()
     }).upload(f))
 }
 
@@ -73,6 +73,7 @@ impl VrContext {
         self.disp.borrow_mut().start_present(Some(VRFramebufferAttributes {
             multiview: false,
             depth: false,
+            // the submitted texture is already resolved (see `HdrTarget::new_multisampled`)
             multisampling: false,
         }));
     }
@@ -88,6 +89,15 @@ impl VrContext {
        size_from_data(&self.disp.borrow().data())
     }
 
+    /// Get the largest render size of a single eye, which is the size needed
+    /// for each eye's draw target.
+    pub fn retrieve_eye_size(&mut self) -> (u32, u32) {
+        let data = self.disp.borrow().data();
+        let l = &data.left_eye_parameters;
+        let r = &data.right_eye_parameters;
+        (l.render_width.max(r.render_width), l.render_height.max(r.render_height))
+    }
+
     /// Synchronize with the hardware, returning transient details about the VR
     /// system at the specific moment in time. This data can be used directly or
     /// to update state variables.
@@ -150,7 +160,6 @@ impl VrContext {
                         eye: moment.inverse_stage * left_view.try_inverse().unwrap() * Point3::origin(),
                         view: left_view * moment.stage,
                         proj: left_projection,
-                        clip_offset: -0.5,
                         clip: Rect {
                             x: 0,
                             y: 0,
@@ -162,7 +171,6 @@ impl VrContext {
                         eye: moment.inverse_stage * right_view.try_inverse().unwrap() * Point3::origin(),
                         view: right_view * moment.stage,
                         proj: right_projection,
-                        clip_offset: 0.5,
                         clip: Rect {
                             x: data.left_eye_parameters.render_width as u16,
                             y: 0,
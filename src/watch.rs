@@ -0,0 +1,164 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use ::Error;
+use ::draw::shader_dir;
+
+/// How often watched files are checked for changes, in milliseconds
+pub const POLL_MILLIS: u64 = 500;
+
+/// The modification time of a file, or `None` if it is missing.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The modification times of a set of files, which are polled rather than
+/// watched with platform-specific APIs.
+struct Stamps {
+    times: Vec<(PathBuf, Option<SystemTime>)>,
+    polled: Instant,
+}
+
+impl Stamps {
+    fn new(paths: Vec<PathBuf>) -> Stamps {
+        Stamps {
+            times: paths.into_iter().map(|p| { let t = modified(&p); (p, t) }).collect(),
+            polled: Instant::now(),
+        }
+    }
+
+    /// Whether it is time to poll again, i.e. whether `POLL_MILLIS` have passed since the last poll.
+    fn due(&mut self) -> bool {
+        if self.polled.elapsed() < Duration::from_millis(POLL_MILLIS) { return false }
+        self.polled = Instant::now();
+        true
+    }
+
+    /// Replace the stamps with those of the given files, returning whether anything changed.
+    fn check(&mut self, paths: Vec<PathBuf>) -> bool {
+        let new = Stamps::new(paths);
+        let changed = new.times != self.times;
+        self.times = new.times;
+        changed
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        self.times.iter().map(|t| t.0.clone()).collect()
+    }
+}
+
+/// The files in the runtime shader directory, if one is set.
+fn shader_files() -> Vec<PathBuf> {
+    let mut files: Vec<_> = shader_dir()
+        .and_then(|d| fs::read_dir(d).ok())
+        .into_iter()
+        .flat_map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Watches the runtime shader directory (see `draw::set_shader_dir`) for changes, so that
+/// painters can be rebuilt with `Painter::reload`. For example:
+///
+/// ```ignore
+/// if watcher.changed() {
+///     painter.reload(&mut factory);
+/// }
+/// ```
+pub struct ShaderWatcher {
+    stamps: Stamps,
+}
+
+impl ShaderWatcher {
+    /// Start watching the shader files as they are now.
+    pub fn new() -> ShaderWatcher {
+        ShaderWatcher {
+            stamps: Stamps::new(shader_files()),
+        }
+    }
+
+    /// Whether any shader file has been changed, added or removed since the last call
+    /// (or since the watcher was created). This is cheap to call every frame.
+    pub fn changed(&mut self) -> bool {
+        self.stamps.due() && self.stamps.check(shader_files())
+    }
+}
+
+impl Default for ShaderWatcher {
+    fn default() -> ShaderWatcher { ShaderWatcher::new() }
+}
+
+/// A value loaded from files, e.g. a mesh or texture loaded with the functions in `load`,
+/// that is loaded again when any of the files change. The value is reached through `Deref`.
+pub struct Watched<F, T> {
+    value: T,
+    stamps: Stamps,
+    load: Box<FnMut(&mut F) -> Result<T, Error>>,
+}
+
+impl<F, T> Watched<F, T> {
+    /// Load a value with the given function, which is called again whenever one of
+    /// the given files changes. For example:
+    ///
+    /// ```ignore
+    /// let tex = Watched::new(&mut factory, &["albedo.png"], move |f| load::open_rgba8(f, "albedo.png", sampler.clone()))?;
+    /// ```
+    pub fn new<P, L>(f: &mut F, paths: &[P], mut load: L) -> Result<Watched<F, T>, Error>
+        where P: AsRef<Path>, L: FnMut(&mut F) -> Result<T, Error> + 'static
+    {
+        // Stamp first, so that changes made while loading are picked up
+        let stamps = Stamps::new(paths.iter().map(|p| p.as_ref().to_owned()).collect());
+        Ok(Watched {
+            value: load(f)?,
+            stamps: stamps,
+            load: Box::new(load),
+        })
+    }
+
+    /// Load the value again if any of its files have changed, returning whether it was
+    /// replaced. If loading fails, the error is logged and the old value is kept.
+    pub fn update(&mut self, f: &mut F) -> bool {
+        let paths = self.stamps.paths();
+        if !self.stamps.due() || !self.stamps.check(paths) { return false }
+        match (self.load)(f) {
+            Ok(v) => {
+                self.value = v;
+                info!("Reloaded {}", self.describe());
+                true
+            },
+            Err(e) => {
+                error!("Could not reload {}: {}", self.describe(), e);
+                false
+            },
+        }
+    }
+
+    /// The watched files, for messages.
+    fn describe(&self) -> String {
+        self.stamps.times.iter()
+            .map(|t| t.0.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl<F, T> Deref for Watched<F, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T { &self.value }
+}
+
+#[test]
+fn stamps() {
+    let path = ::std::env::temp_dir().join(format!("flight-watch-{}.txt", ::std::process::id()));
+    fs::write(&path, "a").unwrap();
+    let mut stamps = Stamps::new(vec![path.clone()]);
+    assert!(!stamps.check(vec![path.clone()]));
+    fs::remove_file(&path).unwrap();
+    assert!(stamps.check(vec![path.clone()]));
+    assert!(!stamps.check(vec![path.clone()]));
+    assert!(stamps.check(vec![]));
+}